@@ -0,0 +1,197 @@
+//! Criterion benchmarks of the engine, the csv parsing, the rendering of the state and the server round-trip.
+//! Transactions are generated with a fixed seed, so the runs are comparable.
+//! `cargo bench --bench engine`, see the README for the baselines
+
+use case::bind_server;
+use case::config::Config;
+use case::db::Db;
+use case::db::transaction::{Transaction, TransactionType};
+use case::parse::Transactions;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use hyper::{Body, Client, Method, Request};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rust_decimal::Decimal;
+
+use std::fmt::Write;
+
+
+const TRANSACTIONS: u32 = 100_000;
+const CLIENTS: u16 = 1000;
+
+fn amount(rng: &mut StdRng) -> Decimal {
+    Decimal::new(rng.gen_range(1..1_000_000), 4)
+}
+
+/// Deposits and withdrawals in the proportion, every client gets a deposit first
+fn deposits_withdrawals(withdrawals: f64) -> Vec<Transaction> {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    (0..TRANSACTIONS).map(|tx| {
+        let client = (tx % CLIENTS as u32) as u16;
+        let r#type = if tx >= CLIENTS as u32 && rng.gen_bool(withdrawals) {
+            TransactionType::Withdrawal
+        } else {
+            TransactionType::Deposit
+        };
+        Transaction::new(r#type, client, tx, Some(amount(&mut rng)), false)
+    }).collect()
+}
+
+/// Deposits, then disputes of them, half resolved and some charged back
+fn disputes() -> Vec<Transaction> {
+    let mut transactions = deposits_withdrawals(0.0);
+    let mut rng = StdRng::seed_from_u64(42);
+
+    for tx in 0..TRANSACTIONS / 2 {
+        let client = (tx % CLIENTS as u32) as u16;
+        transactions.push(Transaction::new(TransactionType::Dispute, client, tx, None, false));
+        let outcome = match rng.gen_range(0..10) {
+            0 => TransactionType::Chargeback,
+            1..=5 => TransactionType::Resolve,
+            _ => continue,
+        };
+        transactions.push(Transaction::new(outcome, client, tx, None, false));
+    }
+
+    transactions
+}
+
+/// Deposits, then transfers between the clients
+fn transfers() -> Vec<Transaction> {
+    let mut transactions = deposits_withdrawals(0.0);
+    let mut rng = StdRng::seed_from_u64(42);
+
+    for tx in TRANSACTIONS..2 * TRANSACTIONS {
+        let client = rng.gen_range(0..CLIENTS);
+        let to = (client + rng.gen_range(1..CLIENTS)) % CLIENTS;
+        transactions.push(Transaction::new(TransactionType::Transfer, client, tx, Some(amount(&mut rng)), false).with_counterparty(to));
+    }
+
+    transactions
+}
+
+/// Fees, limits and fraud rules, all at once
+fn configured() -> Config {
+    serde_json::from_str(r#"{
+        "fees": {"house_account": 0, "fees": {"withdrawal": {"flat": 0.01, "percentage": 0.1}, "deposit": {"flat": 0.01}}},
+        "limits": {"global": {"max_withdrawal": 50, "rolling_withdrawals": {"window_ms": 1000, "max_volume": 200}}},
+        "rules": {"dispute_burst": {"window_ms": 1000, "disputes": 3}, "drain_after_deposit": 90}
+    }"#).unwrap()
+}
+
+fn process(c: &mut Criterion) {
+    let mixes = [
+        ("deposits", deposits_withdrawals(0.0), Config::default()),
+        ("deposits_withdrawals", deposits_withdrawals(0.5), Config::default()),
+        ("disputes", disputes(), Config::default()),
+        ("transfers", transfers(), Config::default()),
+        ("configured", deposits_withdrawals(0.5), configured()),
+    ];
+
+    let mut group = c.benchmark_group("process_new_transaction");
+    group.sample_size(10);
+
+    for (name, transactions, config) in mixes.iter() {
+        group.throughput(Throughput::Elements(transactions.len() as u64));
+        group.bench_function(*name, |b| b.iter_batched(
+            || (Db::new(config), transactions.clone()),
+            |(mut db, transactions)| {
+                for t in transactions {
+                    let _ = db.process_new_transaction(t);
+                }
+                db
+            },
+            BatchSize::LargeInput,
+        ));
+    }
+
+    group.finish();
+}
+
+/// Csv of deposits and withdrawals, without spaces, so it could be read without trimming
+fn csv_file() -> String {
+    let mut csv = "type,client,tx,amount\n".to_string();
+    for t in deposits_withdrawals(0.5) {
+        let _ = writeln!(csv, "{},{},{},{}", t.get_type(), t.client(), t.tx(), t.amount().unwrap_or_default());
+    }
+    csv
+}
+
+fn csv(c: &mut Criterion) {
+    let file = csv_file();
+
+    let mut group = c.benchmark_group("csv");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(file.len() as u64));
+
+    for (name, trim) in [("trim_all", csv::Trim::All), ("no_trim", csv::Trim::None)] {
+        group.bench_function(name, |b| b.iter(|| {
+            let mut rdr = csv::ReaderBuilder::new()
+                .trim(trim)
+                .from_reader(file.as_bytes());
+            rdr.deserialize::<Transaction>().filter(Result::is_ok).count()
+        }));
+    }
+
+    group.bench_function("fast_path", |b| b.iter(|| {
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file.as_bytes());
+        Transactions::new(rdr).filter(Result::is_ok).count()
+    }));
+
+    group.finish();
+}
+
+fn display(c: &mut Criterion) {
+    let mut db = Db::new(&Config::default());
+    for t in deposits_withdrawals(0.5) {
+        let asset = if t.tx() % 2 == 0 { "EUR" } else { "USD" };
+        let _ = db.process_new_transaction(t.with_asset(asset));
+    }
+
+    c.bench_function("display", |b| b.iter(|| db.to_string()));
+}
+
+fn server(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (addr, server) = {
+        let _guard = rt.enter();
+        bind_server(([127, 0, 0, 1], 0), &Config::default(), false)
+    };
+    rt.spawn(server);
+
+    let url = format!("http://{}/", addr);
+    let client = Client::new();
+    let mut tx = 0u32;
+
+    let mut group = c.benchmark_group("server");
+
+    group.bench_function("post_csv", |b| b.iter(|| {
+        tx = tx.wrapping_add(1);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(&url)
+            .header("content-type", "text/csv")
+            .body(Body::from(format!("deposit,{},{},1.0", tx % CLIENTS as u32, tx)))
+            .unwrap();
+        rt.block_on(async {
+            let resp = client.request(req).await.unwrap();
+            hyper::body::to_bytes(resp.into_body()).await.unwrap()
+        })
+    }));
+
+    group.bench_function("get_state", |b| b.iter(|| {
+        rt.block_on(async {
+            let resp = client.get(url.parse().unwrap()).await.unwrap();
+            hyper::body::to_bytes(resp.into_body()).await.unwrap()
+        })
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, process, csv, display, server);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+//! Memory taken by the stored transactions: the plain `HashMap<u32, Transaction>` the accounts used to keep,
+//! with the fields the transaction had then and with today's `Transaction`, against the compact `TransactionStore`.
+//! `cargo bench --bench memory`, the amount of transactions could be set by `CASE_BENCH_TRANSACTIONS` (10M by default)
+
+use case::db::account::store::TransactionStore;
+use case::db::transaction::{Transaction, TransactionType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+
+/// System allocator, counting the allocated bytes
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Layout of the transaction the accounts kept, before the assets, transfers, timestamps and fees
+#[allow(dead_code)]
+struct BaselineTransaction {
+    r#type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    subject_of_dispute: bool,
+}
+
+fn deposit(tx: u32) -> Transaction {
+    Transaction::new(TransactionType::Deposit, 1, tx, Some(dec!(1.2345)), false)
+}
+
+/// Bytes allocated by `f` and still held by its result
+fn measure<T>(f: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let result = f();
+    (ALLOCATED.load(Ordering::Relaxed) - before, result)
+}
+
+fn report(name: &str, bytes: usize, transactions: u32) {
+    println!("{:<30} {:>8} MiB, {:>6.1} bytes per transaction",
+        name,
+        bytes / (1024 * 1024),
+        bytes as f64 / transactions as f64,
+    );
+}
+
+fn main() {
+    let transactions: u32 = std::env::var("CASE_BENCH_TRANSACTIONS").ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(10_000_000);
+
+    println!("{} transactions", transactions);
+
+    let (bytes, map) = measure(|| {
+        let mut map = HashMap::new();
+        for tx in 0..transactions {
+            map.insert(tx, BaselineTransaction { r#type: TransactionType::Deposit, client: 1, tx, amount: Some(dec!(1.2345)), subject_of_dispute: false });
+        }
+        map
+    });
+    report("HashMap, baseline Transaction", bytes, transactions);
+    drop(map);
+
+    let (bytes, map) = measure(|| {
+        let mut map = HashMap::new();
+        for tx in 0..transactions {
+            map.insert(tx, deposit(tx));
+        }
+        map
+    });
+    report("HashMap, current Transaction", bytes, transactions);
+    drop(map);
+
+    let (bytes, store) = measure(|| {
+        let mut store = TransactionStore::new(1, Arc::default());
+        for tx in 0..transactions {
+            store.insert(deposit(tx));
+        }
+        store
+    });
+    report("TransactionStore", bytes, transactions);
+    drop(store);
+}
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use case::parse_csv_line;
+use case::db::Db;
+
+// Arbitrary bytes as a single csv line, the server's `csv` filter path
+fuzz_target!(|data: &[u8]| {
+    if let Some(Ok(transaction)) = parse_csv_line(data) {
+        let mut db = Db::default();
+        let _ = db.process_new_transaction(transaction);
+    }
+});
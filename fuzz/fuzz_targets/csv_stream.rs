@@ -0,0 +1,30 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use case::process_reader;
+use case::config::Config;
+use case::db::transaction::Transaction;
+use case::parse::Transactions;
+use rust_decimal::Decimal;
+
+fn reader(data: &[u8]) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data)
+}
+
+/// Everything parsed from a record, amounts are compared by the value
+fn fields(t: Transaction) -> (String, u16, u32, Option<Decimal>, String, Option<u16>, Option<u64>) {
+    (t.get_type().to_string(), t.client(), t.tx(), t.amount(), t.asset().to_string(), t.counterparty(), t.timestamp())
+}
+
+// Arbitrary bytes as a whole csv file, the `from_file` / `from_stdin` path.
+// The fast path must accept and reject the same records as serde
+fuzz_target!(|data: &[u8]| {
+    let expected: Vec<_> = reader(data).deserialize::<Transaction>().map(|r| r.ok().map(fields)).collect();
+    let actual: Vec<_> = Transactions::new(reader(data)).map(|r| r.ok().map(fields)).collect();
+    assert_eq!(expected, actual);
+
+    let db = process_reader(data, &Config::default(), false).unwrap();
+    let _ = format!("{}", db);
+});
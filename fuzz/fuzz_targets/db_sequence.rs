@@ -0,0 +1,68 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+
+use rust_decimal::Decimal;
+
+use case::db::Db;
+use case::db::transaction::{Transaction, TransactionType};
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Transfer,
+}
+
+/// Structured transaction. Clients and txs are kept in a small range, so the operations hit each other
+#[derive(Debug, Arbitrary)]
+struct Op {
+    kind: Kind,
+    client: u8,
+    tx: u8,
+    /// Mantissa and scale of the amount
+    amount: Option<(i64, u8)>,
+    /// Second asset instead of the default one
+    other_asset: bool,
+    /// Counterparty of a transfer
+    counterparty: Option<u8>,
+}
+
+impl From<Op> for Transaction {
+    fn from(op: Op) -> Self {
+        let r#type = match op.kind {
+            Kind::Deposit => TransactionType::Deposit,
+            Kind::Withdrawal => TransactionType::Withdrawal,
+            Kind::Dispute => TransactionType::Dispute,
+            Kind::Resolve => TransactionType::Resolve,
+            Kind::Chargeback => TransactionType::Chargeback,
+            Kind::Transfer => TransactionType::Transfer,
+        };
+        let amount = op.amount.map(|(num, scale)| Decimal::new(num, (scale % 29) as u32));
+
+        let mut t = Transaction::new(r#type, (op.client % 8) as u16, (op.tx % 32) as u32, amount, false);
+        if let Some(counterparty) = op.counterparty {
+            t = t.with_counterparty((counterparty % 8) as u16);
+        }
+        if op.other_asset {
+            t.with_asset("EUR")
+        } else {
+            t
+        }
+    }
+}
+
+// Arbitrary sequences of transactions into the engine. Errors are fine, panics and unbalanced books are not
+fuzz_target!(|ops: Vec<Op>| {
+    let mut db = Db::default();
+    for op in ops {
+        let _ = db.process_new_transaction(op.into());
+    }
+    let _ = format!("{}", db);
+
+    let tb = db.trial_balance();
+    assert!(tb.is_balanced(), "{}", tb);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use case::db::Db;
+use case::db::transaction::Transaction;
+
+// Arbitrary bytes as a json body, the server's `json` filter path (warp uses `serde_json::from_slice`)
+fuzz_target!(|data: &[u8]| {
+    if let Ok(transaction) = serde_json::from_slice::<Transaction>(data) {
+        let mut db = Db::default();
+        let _ = db.process_new_transaction(transaction);
+    }
+});
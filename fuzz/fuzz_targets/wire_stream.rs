@@ -0,0 +1,30 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+use case::db::Db;
+use case::db::transaction::{Transaction, TransactionType};
+use case::wire::{self, Frames, PREFIX};
+
+fn fields(t: &Transaction) -> (TransactionType, u16, u32, Option<Decimal>, String, Option<u16>, Option<u64>) {
+    (t.get_type().clone(), t.client(), t.tx(), t.amount(), t.asset().to_string(), t.counterparty(), t.timestamp())
+}
+
+// Arbitrary bytes as a stream of binary frames, pushed in chunks like the server's wire listener reads them.
+// Decoded transactions have to survive another round-trip
+fuzz_target!(|data: &[u8]| {
+    let chunk = data.first().map_or(1, |b| *b as usize + 1);
+    let mut frames = Frames::new();
+    let mut db = Db::default();
+
+    for part in data.chunks(chunk) {
+        frames.push(part);
+        for transaction in (&mut frames).flatten() {
+            let frame = wire::encode(&transaction).expect("Decoded asset fits");
+            let again = wire::decode(&frame[PREFIX..]).expect("Encoded frame decodes");
+            assert_eq!(fields(&again), fields(&transaction));
+
+            let _ = db.process_new_transaction(transaction);
+        }
+    }
+});
@@ -1,7 +1,10 @@
 extern crate clap;
 use clap::{Arg, App, SubCommand};
 
-use case::{run_server_fuzz, gen_lines};
+use case::{run_server_fuzz, run_server_replay, run_wire_fuzz, gen_lines, gen_frames, LoadConfig, RetryPolicy, WireTarget};
+use case::config::Config;
+
+use std::time::Duration;
 
 
 
@@ -22,15 +25,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                         .help("total payload lines to make")
                         .takes_value(true))
                 )
+        .subcommand(SubCommand::with_name("wire")
+                    .about("Makes binary frames of test data. Writes them into stdout, or sends them to the server in a single stream. Typically: ./fuzzer wire -t 127.0.0.1:3031")
+                    .version("1.0")
+                    .author("Daniil N. <daniil.naumetc@gmail.com>")
+                    .arg(Arg::with_name("frames")
+                        .short("n")
+                        .default_value("131072") // 1024 * 128
+                        .help("total frames to make")
+                        .takes_value(true))
+                    .arg(Arg::with_name("tcp")
+                        .short("t")
+                        .help("address with port of the raw TCP listener of the server")
+                        .takes_value(true))
+                    .arg(Arg::with_name("url")
+                        .short("u")
+                        .help("address with port of the server, the frames are streamed as a http body")
+                        .conflicts_with("tcp")
+                        .takes_value(true))
+                )
         .subcommand(SubCommand::with_name("server")
                 .about("fuzzes the from csv")
                 .version("1.0")
                 .author("Daniil N. <daniil.naumetc@gmail.com>")
                 .arg(Arg::with_name("from_csv")
                     .short("f")
-                    .default_value("/tmp/transactions.csv")
-                    .help("catches the data from csv, which is a much faster way, than generating on spot")
+                    .help("catches the data from csv, which is a much faster way, than generating on spot. Afterwards compares the server state with the offline one")
                     .takes_value(true))
+                .arg(Arg::with_name("config")
+                    .short("C")
+                    .help("json config the server runs with, to compute the offline result of the replay")
+                    .takes_value(true))
+                .arg(Arg::with_name("in_order")
+                    .short("o")
+                    .help("replays the csv strictly in order with a single worker, instead of sharding it by client across the concurrent workers"))
                 .arg(Arg::with_name("concurrent")
                     .short("c")
                     .default_value("64")
@@ -41,6 +69,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                     .default_value("131072") // 1024 * 128
                     .help("total requests to make, please make sure the number is 2^n, like 2, 4, 8, 16...")
                     .takes_value(true))
+                .arg(Arg::with_name("rate")
+                    .short("r")
+                    .help("target requests per second. Requests are sent on schedule (open loop), no matter how fast the server responds. Concurrency is ignored")
+                    .takes_value(true))
+                .arg(Arg::with_name("max_in_flight")
+                    .short("m")
+                    .default_value("10000")
+                    .help("most requests in flight with a target rate, the scheduled ones over it are skipped and counted")
+                    .takes_value(true))
+                .arg(Arg::with_name("duration")
+                    .short("d")
+                    .help("runs for the amount of seconds, instead of making a fixed amount of requests")
+                    .takes_value(true))
+                .arg(Arg::with_name("report")
+                    .short("j")
+                    .help("writes the json report into the file, to compare the runs between builds")
+                    .takes_value(true))
+                .arg(Arg::with_name("timeout")
+                    .short("t")
+                    .default_value("5000")
+                    .help("timeout of a single request attempt in milliseconds")
+                    .takes_value(true))
+                .arg(Arg::with_name("retries")
+                    .short("e")
+                    .default_value("3")
+                    .help("retries of a failed or timed out request, with an exponential backoff")
+                    .takes_value(true))
+                .arg(Arg::with_name("backoff")
+                    .short("b")
+                    .default_value("10")
+                    .help("delay before the first retry in milliseconds, doubled for every next one")
+                    .takes_value(true))
                 .arg(Arg::with_name("statistics")
                     .short("s")
                     .help("prints performance info during the run"))
@@ -55,23 +115,72 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     match matches.subcommand() {
         ("server",  Some(sub_m)) => {
             let n: u64 = sub_m.value_of("requests").and_then(|s| s.parse::<u64>().ok()).unwrap_or(1024*128);
-            let concurrent: u64 = sub_m.value_of("concurrent").and_then(|s| s.parse().ok()).unwrap_or(128); // 128
+            let concurrent: u64 = concurrent(sub_m.value_of("concurrent"))?;
             let statistics: bool = sub_m.is_present("statistics");
             let url: &str = sub_m.value_of("url").expect("Setup the default value, so it should exist");
+
+            if let Some(location) = sub_m.value_of("from_csv") {
+                let in_order: bool = sub_m.is_present("in_order");
+                let config = match sub_m.value_of("config") {
+                    Some(config) => Config::from_file(config)?,
+                    None => Config::default(),
+                };
+                return run_server_replay(url, location, &config, concurrent, in_order, statistics).await
+            }
             
-            run_server_fuzz(url, n, concurrent, statistics).await
+            let config = LoadConfig {
+                url: url.to_string(),
+                requests: n,
+                concurrent,
+                rate: sub_m.value_of("rate").and_then(|s| s.parse::<f64>().ok()).filter(|r| *r > 0.0),
+                max_in_flight: sub_m.value_of("max_in_flight").and_then(|s| s.parse().ok()).unwrap_or(10000),
+                duration: sub_m.value_of("duration").and_then(|s| s.parse::<f64>().ok()).map(Duration::from_secs_f64),
+                retry: RetryPolicy {
+                    timeout: sub_m.value_of("timeout").and_then(|s| s.parse().ok()).map(Duration::from_millis).unwrap_or_else(|| RetryPolicy::default().timeout),
+                    retries: sub_m.value_of("retries").and_then(|s| s.parse().ok()).unwrap_or_else(|| RetryPolicy::default().retries),
+                    backoff: sub_m.value_of("backoff").and_then(|s| s.parse().ok()).map(Duration::from_millis).unwrap_or_else(|| RetryPolicy::default().backoff),
+                },
+                statistics,
+                report: sub_m.value_of("report").map(|s| s.to_string()),
+            };
+
+            run_server_fuzz(&config).await?;
+            Ok(())
         },
         ("csv",   Some(sub_m)) => {
             let n: u64 = sub_m.value_of("lines").and_then(|s| s.parse::<u64>().ok()).unwrap_or(1024*128);
             gen_lines(n);
             Ok(())
         },
+        ("wire",   Some(sub_m)) => {
+            let n: u64 = sub_m.value_of("frames").and_then(|s| s.parse::<u64>().ok()).unwrap_or(1024*128);
+            let target = match (sub_m.value_of("tcp"), sub_m.value_of("url")) {
+                (Some(addr), _) => WireTarget::Tcp(addr.to_string()),
+                (None, Some(url)) => WireTarget::Http(url.to_string()),
+                (None, None) => {
+                    gen_frames(n)?;
+                    return Ok(())
+                },
+            };
+            run_wire_fuzz(&target, n).await
+        },
         _ => {
             Ok(())
         },
     }
 }
 
+/// Number of the concurrent workers, at least one
+fn concurrent(value: Option<&str>) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    match value {
+        Some(value) => match value.parse() {
+            Ok(n) if n >= 1 => Ok(n),
+            _ => Err(format!("Invalid number of concurrent requests, at least 1 is required: {}", value).into()),
+        },
+        None => Ok(128),
+    }
+}
+
 
 // 2400/s
 // ./target/release/server_fuzzer  1,54s user 1,27s system 13% cpu 21,254 total async 20
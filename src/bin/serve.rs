@@ -1,7 +1,10 @@
 extern crate clap;
 use clap::{Arg, App, SubCommand};
 
-use case::{run_server, from_stdin, from_file};
+use case::{run_server, Listeners, from_stdin, from_files, db_from_files, process_reader, write_flag_report, write_trial_balance};
+use case::config::Config;
+
+use std::net::SocketAddr;
 
 
 #[tokio::main]
@@ -12,10 +15,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .author("Daniil N. <daniil.naumetc@gmail.com>")
         .about("Main implementation")
         .arg(Arg::with_name("location")
+            .help("csv files, processed in sequence. .gz and .zst are decompressed")
+            .multiple(true)
             .index(1))
         .arg(Arg::with_name("verbose")
             .short("v")
             .help("turns on verbose mode"))
+        .arg(Arg::with_name("config")
+            .short("c")
+            .help("json config of the engine (fees, etc.)")
+            .takes_value(true))
+        .arg(Arg::with_name("flags")
+            .short("f")
+            .help("writes the report of the accounts flagged by the fraud rules into the file")
+            .takes_value(true))
+        .arg(Arg::with_name("trial_balance")
+            .short("t")
+            .help("writes the trial balance of the journal into the file")
+            .takes_value(true))
         .subcommand(SubCommand::with_name("server")
                 .about("runs a server")
                 .version("1.0")
@@ -25,30 +42,79 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                     .default_value("3030")
                     .help("server port")
                     .takes_value(true))
+                .arg(Arg::with_name("wire_port")
+                    .short("w")
+                    .help("port of the raw TCP listener of binary frames, see the README for the format")
+                    .takes_value(true))
+                .arg(Arg::with_name("lines_port")
+                    .short("l")
+                    .help("port of the raw TCP listener of newline-delimited csv or json transactions, limits are in the config")
+                    .takes_value(true))
                 .arg(Arg::with_name("verbose")
                     .short("v")
                     .help("turns on verbose mode"))
+                .arg(Arg::with_name("config")
+                    .short("c")
+                    .help("json config of the engine (fees, etc.)")
+                    .takes_value(true))
             )
         .get_matches();
 
     match matches.subcommand() {
         ("server",  Some(sub_m)) => {
             let port: u16 = sub_m.value_of("port").and_then(|s| s.parse().ok()).unwrap_or(3030);
+            let listeners = Listeners {
+                wire: listener_addr(sub_m.value_of("wire_port"))?,
+                lines: listener_addr(sub_m.value_of("lines_port"))?,
+            };
             let verbose = sub_m.is_present("verbose");
+            let config = load_config(sub_m.value_of("config"))?;
             
-            run_server(port, verbose).await;
+            run_server(port, listeners, &config, verbose).await;
 
             Ok(())
         },
         _ => {
             let verbose = matches.is_present("verbose");
-            if let Some(location) = matches.value_of("location") {
-                from_file(location, verbose)?;
+            let config = load_config(matches.value_of("config"))?;
+            if matches.is_present("flags") || matches.is_present("trial_balance") {
+                let db = match matches.values_of("location") {
+                    Some(locations) => db_from_files(&locations.collect::<Vec<_>>(), &config, verbose)?,
+                    None => process_reader(std::io::stdin(), &config, verbose)?,
+                };
+                println!("{}", db);
+                if let Some(report) = matches.value_of("flags") {
+                    write_flag_report(&db, report)?;
+                }
+                if let Some(report) = matches.value_of("trial_balance") {
+                    write_trial_balance(&db, report)?;
+                }
+            } else if let Some(locations) = matches.values_of("location") {
+                from_files(&locations.collect::<Vec<_>>(), &config, verbose)?;
             } else {
-                from_stdin(verbose)?;
+                from_stdin(&config, verbose)?;
             }
             
             Ok(())
         },
     }
-}
\ No newline at end of file
+}
+
+/// Reads the config, if the location is given. Default one otherwise
+fn load_config(location: Option<&str>) -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
+    match location {
+        Some(location) => Config::from_file(location),
+        None => Ok(Config::default()),
+    }
+}
+
+/// Local address of a raw TCP listener, if the port is given
+fn listener_addr(port: Option<&str>) -> Result<Option<SocketAddr>, Box<dyn std::error::Error + Send + Sync>> {
+    match port {
+        Some(port) => {
+            let port: u16 = port.parse().map_err(|_| format!("Invalid listener port: {}", port))?;
+            Ok(Some(([127, 0, 0, 1], port).into()))
+        },
+        None => Ok(None),
+    }
+}
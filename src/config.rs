@@ -0,0 +1,79 @@
+use serde::Deserialize;
+
+use std::fs::File;
+
+use crate::db::fees::FeeSchedule;
+use crate::db::limits::Limits;
+use crate::db::rules::Rules;
+use crate::db::precision::Precision;
+use crate::db::retention::Retention;
+use crate::idempotency::Idempotency;
+use crate::lines::LineLimits;
+use crate::webhooks::Webhooks;
+
+
+/// Configuration of the engine, loaded from a json file at startup
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Fees charged per transaction type
+    pub fees: FeeSchedule,
+    /// Risk limits, global and per client
+    pub limits: Limits,
+    /// Fraud rules, flagging suspicious accounts
+    pub rules: Rules,
+    /// Decimals and rounding per asset
+    pub precision: Precision,
+    /// How long the transactions are kept to be disputed
+    pub retention: Retention,
+    /// Responses remembered by the server for the idempotency keys
+    pub idempotency: Idempotency,
+    /// Limits of the line-protocol listener of the server
+    pub lines: LineLimits,
+    /// Webhooks the server sends on account locks, chargebacks and disputes
+    pub webhooks: Webhooks,
+    /// Window in milliseconds to reorder the input by the timestamps, in the file mode. No reordering if missing
+    pub reorder_window: Option<u64>,
+}
+
+impl Config {
+
+    /// Reads the configuration from a json file and validates it
+    pub fn from_file(location: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = File::open(location)?;
+        let config: Self = serde_json::from_reader(file)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks the settings which are valid json, but don't make sense together
+    pub fn validate(&self) -> Result<(), String> {
+        let house = self.fees.house_account;
+        if !self.fees.is_empty() && self.limits.clients.contains_key(&house) {
+            return Err(format!("Client {} has limits, but it's the house account", house))
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn house_account_isnt_a_client() {
+        let config: Config = serde_json::from_str(r#"{
+            "fees": {"house_account": 7, "fees": {"withdrawal": {"flat": 1}}},
+            "limits": {"clients": {"7": {"max_withdrawal": 10}}}
+        }"#).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = serde_json::from_str(r#"{
+            "limits": {"clients": {"65535": {"max_withdrawal": 10}}}
+        }"#).unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,29 @@
+use crate::Monetary;
+
+
+/// Funds of a single asset inside of an account
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+
+    /// Available funds
+    pub available: Monetary,
+
+    /// Held funds
+    pub held: Monetary,
+}
+
+impl Balance {
+
+    /// Constructor
+    pub fn new(available: Monetary, held: Monetary) -> Self {
+        Self {
+            available,
+            held,
+        }
+    }
+
+    /// Total amount: available + held
+    pub fn total(&self) -> Monetary {
+        self.available + self.held
+    }
+}
@@ -15,6 +15,42 @@ pub enum AccountError {
     TransactionIsNotSubjectOfDispute,
     IAmNotTheOwner,
     TransactionNotFound,
+    /// Transaction was evicted by the retention policy and can't be disputed anymore
+    TransactionEvicted,
+    /// Transfers and disputes of transfers involve two accounts, so they can't be executed by a single one
+    TransferNeedsCounterparty,
+    /// Single withdrawal is above the limit - contains the limit
+    WithdrawalLimit(Monetary),
+    /// Recent withdrawals volume would be above the limit - contains what is still allowed
+    WithdrawalVolumeLimit(Monetary),
+    /// Account has opened too many disputes - contains the limit
+    DisputesLimit(u32),
+    /// Available funds would go below the minimum balance - contains the minimum
+    MinimumBalance(Monetary),
+}
+
+impl AccountError {
+
+    /// Name of the variant, as a metrics label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AccountError::TooMuch(_) => "too_much",
+            AccountError::NegativeAmount => "negative_amount",
+            AccountError::AccountLocked => "account_locked",
+            AccountError::TransactionAlreadyExists => "transaction_already_exists",
+            AccountError::TransactionIsEmpty => "transaction_is_empty",
+            AccountError::TransactionIsSubjectOfDispute => "transaction_is_subject_of_dispute",
+            AccountError::TransactionIsNotSubjectOfDispute => "transaction_is_not_subject_of_dispute",
+            AccountError::IAmNotTheOwner => "i_am_not_the_owner",
+            AccountError::TransactionNotFound => "transaction_not_found",
+            AccountError::TransactionEvicted => "transaction_evicted",
+            AccountError::TransferNeedsCounterparty => "transfer_needs_counterparty",
+            AccountError::WithdrawalLimit(_) => "withdrawal_limit",
+            AccountError::WithdrawalVolumeLimit(_) => "withdrawal_volume_limit",
+            AccountError::DisputesLimit(_) => "disputes_limit",
+            AccountError::MinimumBalance(_) => "minimum_balance",
+        }
+    }
 }
 
 impl fmt::Display for AccountError {
@@ -47,6 +83,24 @@ impl fmt::Display for AccountError {
             AccountError::TransactionNotFound => {
                 write!(f, "Requested transaction not found")
             },
+            AccountError::TransactionEvicted => {
+                write!(f, "Requested transaction is out of the retention window")
+            },
+            AccountError::TransferNeedsCounterparty => {
+                write!(f, "Transfer involves another account, so it can't be processed by a single one")
+            },
+            AccountError::WithdrawalLimit(limit) => {
+                write!(f, "Withdrawal is above the limit: {}", limit)
+            },
+            AccountError::WithdrawalVolumeLimit(allowed) => {
+                write!(f, "Withdrawals volume limit reached, still allowed: {}", allowed)
+            },
+            AccountError::DisputesLimit(limit) => {
+                write!(f, "Too many disputes, the limit is: {}", limit)
+            },
+            AccountError::MinimumBalance(minimum) => {
+                write!(f, "Available funds would go below the minimum balance: {}", minimum)
+            },
         }
     }
 }
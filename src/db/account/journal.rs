@@ -0,0 +1,313 @@
+use std::fmt;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::db::account::balance::Balance;
+use crate::db::precision::Precision;
+use crate::Monetary;
+
+
+/// Book of the double-entry journal. `Available` and `Held` belong to the client whose journal it is, the rest are system books
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Book {
+    /// Available funds of the client
+    Available,
+    /// Held funds of the client
+    Held,
+    /// Funds in the system. Deposits come from it, withdrawals go to it
+    Cash,
+    /// Funds parked while disputed, the charged back ones stay there
+    HeldSuspense,
+    /// Funds returned to the payers by chargebacks, taken from the held funds of the clients
+    ChargebackLoss,
+    /// Funds moving between clients: transfers and fees. Nets to zero
+    Clearing,
+}
+
+impl fmt::Display for Book {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Book::Available => write!(f, "clients-available"),
+            Book::Held => write!(f, "clients-held"),
+            Book::Cash => write!(f, "cash"),
+            Book::HeldSuspense => write!(f, "held-suspense"),
+            Book::ChargebackLoss => write!(f, "chargeback-loss"),
+            Book::Clearing => write!(f, "clearing"),
+        }
+    }
+}
+
+/// Single balanced entry: the amount is debited from one book and credited to another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub asset: String,
+    pub debit: Book,
+    pub credit: Book,
+    pub amount: Monetary,
+}
+
+impl Posting {
+
+    /// Applies the client legs of the posting to the balance. Client books are credit-normal
+    pub fn apply(&self, balance: &mut Balance) {
+        for (book, amount) in [(self.debit, -self.amount), (self.credit, self.amount)] {
+            match book {
+                Book::Available => balance.available += amount,
+                Book::Held => balance.held += amount,
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Debit and credit turnover of a book
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Turnover {
+    pub debit: Monetary,
+    pub credit: Monetary,
+}
+
+impl Turnover {
+
+    /// Debit minus credit
+    pub fn net(&self) -> Monetary {
+        self.debit - self.credit
+    }
+}
+
+/// Postings of an account. The old ones could be folded into the opening turnovers, to keep it bounded
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    /// Turnover per asset and book of the folded postings
+    opening: BTreeMap<(String, Book), Turnover>,
+    /// Postings folded so far
+    folded: usize,
+    postings: VecDeque<Posting>,
+}
+
+impl Journal {
+
+    /// Adds the posting
+    pub fn push(&mut self, posting: Posting) {
+        self.postings.push_back(posting);
+    }
+
+    /// Postings made so far, the folded ones too
+    pub fn len(&self) -> usize {
+        self.folded + self.postings.len()
+    }
+
+    /// Returns `true` if nothing was posted
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the postings after the first `len` ones. The folded ones stay
+    pub fn truncate(&mut self, len: usize) {
+        self.postings.truncate(len.saturating_sub(self.folded));
+    }
+
+    /// Folds all but the last `keep` postings into the opening turnovers
+    pub fn compact(&mut self, keep: usize) {
+        let fold = self.postings.len().saturating_sub(keep);
+        for posting in self.postings.drain(..fold) {
+            self.opening.entry((posting.asset.clone(), posting.debit)).or_default().debit += posting.amount;
+            self.opening.entry((posting.asset, posting.credit)).or_default().credit += posting.amount;
+        }
+        self.folded += fold;
+    }
+}
+
+/// Turnover of every book per asset, summed over the journals of all the accounts
+#[derive(Debug, Clone, Default)]
+pub struct TrialBalance {
+    books: BTreeMap<(String, Book), Turnover>,
+    /// Clients and assets, which balances don't match their journals
+    mismatches: Vec<(u16, String)>,
+    /// To print the amounts
+    precision: Precision,
+}
+
+impl TrialBalance {
+
+    /// Constructor, the amounts are printed with the precision
+    pub fn new(precision: &Precision) -> Self {
+        Self {
+            precision: precision.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds the journal of an account, checking it against the balances of the account
+    pub fn add_account(&mut self, id: u16, journal: &Journal, balances: &BTreeMap<String, Balance>) {
+        let mut replayed: BTreeMap<&str, Balance> = BTreeMap::new();
+
+        for ((asset, book), turnover) in &journal.opening {
+            let total = self.books.entry((asset.clone(), *book)).or_default();
+            total.debit += turnover.debit;
+            total.credit += turnover.credit;
+
+            let balance = replayed.entry(asset).or_default();
+            match book {
+                Book::Available => balance.available += turnover.credit - turnover.debit,
+                Book::Held => balance.held += turnover.credit - turnover.debit,
+                _ => {},
+            }
+        }
+
+        for posting in &journal.postings {
+            let debit = self.books.entry((posting.asset.clone(), posting.debit)).or_default();
+            debit.debit += posting.amount;
+            let credit = self.books.entry((posting.asset.clone(), posting.credit)).or_default();
+            credit.credit += posting.amount;
+
+            posting.apply(replayed.entry(&posting.asset).or_default());
+        }
+
+        for (asset, balance) in balances {
+            if replayed.remove(asset.as_str()).unwrap_or_default() != *balance {
+                self.mismatches.push((id, asset.clone()));
+            }
+        }
+        for (asset, balance) in replayed {
+            if balance != Balance::default() {
+                self.mismatches.push((id, asset.to_string()));
+            }
+        }
+    }
+
+    /// Turnover of the book in the asset
+    pub fn turnover(&self, asset: &str, book: Book) -> Turnover {
+        self.books.get(&(asset.to_string(), book)).copied().unwrap_or_default()
+    }
+
+    /// Clients and assets, which balances don't match their journals
+    pub fn mismatches(&self) -> &[(u16, String)] {
+        &self.mismatches
+    }
+
+    /// Problems of the books, empty if everything balances. Per asset: debits equal credits,
+    /// clearing nets to zero, cash covers the available funds and held-suspense the held ones with the chargeback loss
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self.mismatches.iter()
+            .map(|(id, asset)| format!("client {} asset '{}': balance doesn't match the journal", id, asset))
+            .collect();
+
+        let mut assets: Vec<&String> = self.books.keys().map(|(asset, _)| asset).collect();
+        assets.dedup();
+
+        for asset in assets {
+            let (debit, credit) = self.books.iter()
+                .filter(|((a, _), _)| a == asset)
+                .fold((Monetary::ZERO, Monetary::ZERO), |(d, c), (_, t)| (d + t.debit, c + t.credit));
+            if debit != credit {
+                problems.push(format!("asset '{}': debits {} != credits {}", asset, debit, credit));
+            }
+
+            let clearing = self.turnover(asset, Book::Clearing).net();
+            if !clearing.is_zero() {
+                problems.push(format!("asset '{}': {} doesn't net to zero: {}", asset, Book::Clearing, clearing));
+            }
+
+            let cash = self.turnover(asset, Book::Cash).net();
+            let available = -self.turnover(asset, Book::Available).net();
+            if cash != available {
+                problems.push(format!("asset '{}': {} {} != {} {}", asset, Book::Cash, cash, Book::Available, available));
+            }
+
+            let suspense = self.turnover(asset, Book::HeldSuspense).net();
+            let held = -self.turnover(asset, Book::Held).net();
+            let loss = -self.turnover(asset, Book::ChargebackLoss).net();
+            if suspense != held + loss {
+                problems.push(format!(
+                    "asset '{}': {} {} != {} {} + {} {}",
+                    asset, Book::HeldSuspense, suspense, Book::Held, held, Book::ChargebackLoss, loss
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Returns `true` if the books balance
+    pub fn is_balanced(&self) -> bool {
+        self.problems().is_empty()
+    }
+}
+
+impl fmt::Display for TrialBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "asset, book, debit, credit")?;
+        for ((asset, book), turnover) in &self.books {
+            let precision = self.precision.for_asset(asset);
+            writeln!(f, "{}, {}, {}, {}", asset, book, precision.format(turnover.debit), precision.format(turnover.credit))?;
+        }
+
+        let problems = self.problems();
+        writeln!(f, "balanced: {}", problems.is_empty())?;
+        for problem in problems {
+            writeln!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn posting(debit: Book, credit: Book, amount: Monetary) -> Posting {
+        Posting { asset: String::new(), debit, credit, amount }
+    }
+
+    fn journal_of(postings: &[Posting]) -> Journal {
+        let mut journal = Journal::default();
+        for posting in postings {
+            journal.push(posting.clone());
+        }
+        journal
+    }
+
+    #[test]
+    fn trial_balance() {
+        // Deposit of 10, then a dispute of it
+        let mut journal = journal_of(&[
+            posting(Book::Cash, Book::Available, dec!(10)),
+            posting(Book::Available, Book::Held, dec!(10)),
+            posting(Book::HeldSuspense, Book::Cash, dec!(10)),
+        ]);
+        let mut balances = BTreeMap::new();
+        balances.insert(String::new(), Balance::new(dec!(0), dec!(10)));
+
+        let mut tb = TrialBalance::default();
+        tb.add_account(1, &journal, &balances);
+        assert!(tb.is_balanced(), "{}", tb);
+        assert_eq!(tb.turnover("", Book::Cash), Turnover { debit: dec!(10), credit: dec!(10) });
+
+        // Chargeback of 4 out of the held funds, the loss stays on the books
+        journal.push(posting(Book::Held, Book::ChargebackLoss, dec!(4)));
+        balances.insert(String::new(), Balance::new(dec!(0), dec!(6)));
+
+        let mut tb = TrialBalance::default();
+        tb.add_account(1, &journal, &balances);
+        assert!(tb.is_balanced(), "{}", tb);
+        assert_eq!(tb.turnover("", Book::ChargebackLoss).net(), dec!(-4));
+        assert_eq!(tb.turnover("", Book::HeldSuspense).net(), dec!(10));
+
+        // Folded, the books are the same
+        journal.compact(1);
+        assert_eq!(journal.len(), 4);
+        let mut folded = TrialBalance::default();
+        folded.add_account(1, &journal, &balances);
+        assert!(folded.is_balanced(), "{}", folded);
+        assert_eq!(folded.books, tb.books);
+
+        // A transfer, which wasn't received
+        let mut tb = TrialBalance::default();
+        tb.add_account(1, &journal_of(&[posting(Book::Available, Book::Clearing, dec!(1))]), &BTreeMap::new());
+        assert_eq!(tb.mismatches(), &[(1, String::new())]);
+        assert_eq!(tb.problems().len(), 3);
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::db::account::balance::Balance;
+use crate::db::retention::Retention;
+use crate::db::transaction::TransactionType;
+use crate::db::precision::AssetPrecision;
+use crate::Monetary;
+
+
+/// Single change of a balance, caused by a transaction. Tx ids are per client, so the transaction is `(client, tx)`:
+/// the counterparty of a transfer and the house account record the changes under the client who made them
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: TransactionType,
+    pub asset: String,
+    /// Timestamp of the transaction, if it had one
+    pub timestamp: Option<u64>,
+    /// The change is the fee of the transaction, not the transaction itself
+    pub fee: bool,
+    pub available_delta: Monetary,
+    pub held_delta: Monetary,
+    /// Resulting balance
+    pub balance: Balance,
+}
+
+impl LedgerEntry {
+
+    /// Constructor, the deltas are calculated from the balances before and after the transaction
+    pub fn new(client: u16, tx: u32, r#type: TransactionType, asset: &str, timestamp: Option<u64>, before: Balance, after: Balance) -> Self {
+        Self {
+            client,
+            tx,
+            r#type,
+            asset: asset.to_string(),
+            timestamp,
+            fee: false,
+            available_delta: after.available - before.available,
+            held_delta: after.held - before.held,
+            balance: after,
+        }
+    }
+
+    /// Marks the entry as the fee of the transaction
+    pub fn into_fee(mut self) -> Self {
+        self.fee = true;
+        self
+    }
+
+    /// Formats the entry as a line of the history, amounts with the decimals of the asset.
+    /// The type of a fee is the type of the transaction with `-fee`, e.g. `withdrawal-fee`
+    pub fn format(&self, precision: &AssetPrecision) -> String {
+        format!("{}, {}, {}{}, {}, {}, {}, {}, {}, {}\n",
+            self.client,
+            self.tx,
+            self.r#type,
+            if self.fee { "-fee" } else { "" },
+            self.asset,
+            self.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+            precision.format(self.available_delta),
+            precision.format(self.held_delta),
+            precision.format(self.balance.available),
+            precision.format(self.balance.held),
+        )
+    }
+}
+
+/// Point in the history of an account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    /// Right after the last change made by the transaction `tx` of the `client`
+    /// (disputes share the tx with the disputed transaction)
+    Tx { client: u16, tx: u32 },
+    /// After every change with the timestamp not later than this one, whatever order they were applied in.
+    /// Changes without a timestamp are considered to happen at the time of the previous change
+    Time(u64),
+}
+
+/// Balance changes of an account, in the order they were applied. The ones out of the retention are dropped,
+/// the balances they led to are kept as the opening ones
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    entries: VecDeque<LedgerEntry>,
+    /// Balances after the dropped entries
+    opening: BTreeMap<String, Balance>,
+    /// Time of the last dropped entry, to be the time of the next ones without a timestamp
+    opening_time: u64,
+    /// Latest time of the dropped entries, `None` if nothing was dropped
+    dropped: Option<u64>,
+    /// Latest timestamp seen
+    latest: u64,
+}
+
+impl Ledger {
+
+    /// Adds an entry
+    pub fn record(&mut self, entry: LedgerEntry) {
+        self.latest = self.latest.max(entry.timestamp.unwrap_or_default());
+        self.entries.push_back(entry)
+    }
+
+    /// Kept entries
+    pub fn entries(&self) -> &VecDeque<LedgerEntry> {
+        &self.entries
+    }
+
+    /// Drops the oldest entries while there are more than `max_transactions`,
+    /// or they are older than `max_age_ms` behind the latest timestamp
+    pub fn compact(&mut self, retention: &Retention) {
+        while let Some(first) = self.entries.front() {
+            let over = retention.max_transactions.is_some_and(|max| self.entries.len() > max);
+            let old = match (retention.max_age_ms, first.timestamp) {
+                (Some(age), Some(timestamp)) => self.latest.saturating_sub(timestamp) > age,
+                _ => false,
+            };
+            if !over && !old {
+                break
+            }
+
+            if let Some(e) = self.entries.pop_front() {
+                self.opening_time = e.timestamp.unwrap_or(self.opening_time);
+                self.dropped = Some(self.dropped.map_or(self.opening_time, |dropped| dropped.max(self.opening_time)));
+                self.opening.insert(e.asset, e.balance);
+            }
+        }
+    }
+
+    /// Balances per asset at the point of the history. `None` if the point is not in the history or was dropped
+    pub fn balances_as_of(&self, point: AsOf) -> Option<BTreeMap<String, Balance>> {
+        match point {
+            AsOf::Tx { client, tx } => {
+                let last = self.entries.iter().rposition(|e| e.client == client && e.tx == tx)?;
+
+                let mut balances = self.opening.clone();
+                for e in self.entries.iter().take(last + 1) {
+                    balances.insert(e.asset.clone(), e.balance);
+                }
+
+                Some(balances)
+            },
+            AsOf::Time(at) => {
+                if self.dropped.is_some_and(|dropped| at < dropped) {
+                    return None
+                }
+
+                // The input could be out of the timestamp order, so the deltas of the earlier changes are summed up
+                let mut balances = self.opening.clone();
+                let mut time = self.opening_time;
+                for e in &self.entries {
+                    time = e.timestamp.unwrap_or(time);
+                    if time <= at {
+                        let balance = balances.entry(e.asset.clone()).or_default();
+                        balance.available += e.available_delta;
+                        balance.held += e.held_delta;
+                    }
+                }
+
+                if balances.is_empty() {
+                    None
+                } else {
+                    Some(balances)
+                }
+            },
+        }
+    }
+}
+
+/// Header of the history, as it's printed
+pub const HISTORY_HEADER: &str = "client, tx, type, asset, timestamp, available_delta, held_delta, available, held";
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn balances_as_of() {
+        let mut ledger = Ledger::default();
+        let zero = Balance::default();
+        let ten = Balance::new(dec!(10), dec!(0));
+        let held = Balance::new(dec!(0), dec!(10));
+        let eur = Balance::new(dec!(5), dec!(0));
+
+        ledger.record(LedgerEntry::new(1, 1, TransactionType::Deposit, "", Some(100), zero, ten));
+        ledger.record(LedgerEntry::new(1, 2, TransactionType::Deposit, "EUR", None, zero, eur));
+        ledger.record(LedgerEntry::new(1, 1, TransactionType::Dispute, "", Some(300), ten, held));
+
+        assert_eq!(ledger.entries()[2].available_delta, dec!(-10));
+        assert_eq!(ledger.entries()[2].held_delta, dec!(10));
+
+        let as_of_tx = ledger.balances_as_of(AsOf::Tx { client: 1, tx: 2 }).unwrap();
+        assert_eq!(as_of_tx.get(""), Some(&ten));
+        assert_eq!(as_of_tx.get("EUR"), Some(&eur));
+        assert_eq!(ledger.balances_as_of(AsOf::Tx { client: 1, tx: 1 }).unwrap().get(""), Some(&held));
+        assert!(ledger.balances_as_of(AsOf::Tx { client: 1, tx: 3 }).is_none());
+        // The same tx of another client
+        assert!(ledger.balances_as_of(AsOf::Tx { client: 2, tx: 1 }).is_none());
+
+        assert_eq!(ledger.balances_as_of(AsOf::Time(299)), ledger.balances_as_of(AsOf::Tx { client: 1, tx: 2 }));
+        assert_eq!(ledger.balances_as_of(AsOf::Time(300)).unwrap().get(""), Some(&held));
+        assert!(ledger.balances_as_of(AsOf::Time(99)).is_none());
+    }
+
+    #[test]
+    fn balances_as_of_time_out_of_order() {
+        let mut ledger = Ledger::default();
+        let zero = Balance::default();
+
+        // Applied in this order, but the second one happened first
+        ledger.record(LedgerEntry::new(1, 1, TransactionType::Deposit, "", Some(200), zero, Balance::new(dec!(10), dec!(0))));
+        ledger.record(LedgerEntry::new(1, 2, TransactionType::Deposit, "", Some(100), Balance::new(dec!(10), dec!(0)), Balance::new(dec!(13), dec!(0))));
+        ledger.record(LedgerEntry::new(1, 3, TransactionType::Withdrawal, "", Some(300), Balance::new(dec!(13), dec!(0)), Balance::new(dec!(12), dec!(0))));
+
+        assert!(ledger.balances_as_of(AsOf::Time(99)).is_none());
+        assert_eq!(ledger.balances_as_of(AsOf::Time(150)).unwrap().get(""), Some(&Balance::new(dec!(3), dec!(0))));
+        assert_eq!(ledger.balances_as_of(AsOf::Time(250)).unwrap().get(""), Some(&Balance::new(dec!(13), dec!(0))));
+        assert_eq!(ledger.balances_as_of(AsOf::Time(300)).unwrap().get(""), Some(&Balance::new(dec!(12), dec!(0))));
+    }
+
+    #[test]
+    fn compacts_by_retention() {
+        let mut ledger = Ledger::default();
+        let zero = Balance::default();
+        let ten = Balance::new(dec!(10), dec!(0));
+        let held = Balance::new(dec!(0), dec!(10));
+        let eur = Balance::new(dec!(5), dec!(0));
+
+        ledger.record(LedgerEntry::new(1, 1, TransactionType::Deposit, "", Some(100), zero, ten));
+        ledger.record(LedgerEntry::new(1, 2, TransactionType::Deposit, "EUR", None, zero, eur));
+        ledger.record(LedgerEntry::new(1, 1, TransactionType::Dispute, "", Some(300), ten, held));
+
+        ledger.compact(&Retention { max_age_ms: Some(150), ..Default::default() });
+        assert_eq!(ledger.entries().len(), 2);
+        ledger.compact(&Retention { max_transactions: Some(1), ..Default::default() });
+        assert_eq!(ledger.entries().len(), 1);
+
+        // Dropped points are out of the history, the kept ones start from the dropped balances
+        assert!(ledger.balances_as_of(AsOf::Tx { client: 1, tx: 2 }).is_none());
+        assert!(ledger.balances_as_of(AsOf::Time(99)).is_none());
+        let as_of_tx = ledger.balances_as_of(AsOf::Tx { client: 1, tx: 1 }).unwrap();
+        assert_eq!((as_of_tx.get(""), as_of_tx.get("EUR")), (Some(&held), Some(&eur)));
+        assert_eq!(ledger.balances_as_of(AsOf::Time(200)).unwrap().get(""), Some(&ten));
+        assert_eq!(ledger.balances_as_of(AsOf::Time(300)), Some(as_of_tx));
+    }
+}
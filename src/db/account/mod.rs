@@ -1,27 +1,46 @@
 pub mod error;
+pub mod balance;
+pub mod ledger;
+pub mod journal;
+pub mod store;
 
 use error::AccountError;
+use balance::Balance;
+use ledger::{AsOf, Ledger, LedgerEntry};
+use journal::{Book, Journal, Posting, TrialBalance};
+use store::{Saved, TransactionStore};
 
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
 
 use std::fmt;
 use std::cell::RefCell;
+use std::sync::Arc;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, VecDeque};
 
-use crate::db::transaction::{Transaction, TransactionType};
+use crate::db::transaction::{Transaction, TransactionType, DEFAULT_ASSET};
+use crate::db::limits::Limit;
+use crate::db::precision::Precision;
+use crate::db::retention::Retention;
 use crate::Monetary;
 
-/// Decimal::MAX / 10^4
-const REALLY_REALLY_REALLY_REALLY_A_LOOOOOT: Decimal = dec!(7922816251426433759354396); 
-
 /// Decimal zero
 const ZERO_MONEY: Decimal = dec!(0);
 
 
 
 
+/// State of an account before an operation, to roll the operation back.
+/// Keeps only the parts an operation on the transaction `tx` could change
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    locked: bool,
+    balances: BTreeMap<String, Balance>,
+    journal: usize,
+    transaction: Option<Saved>,
+}
+
 /// Account represents a single client.
 /// The structure also used to keep the transactions associated with the client
 #[derive(Debug)]
@@ -33,25 +52,60 @@ pub struct Account {
     /// If the account is blocked
     locked: RefCell<bool>,
 
-    /// Available funds
-    available: RefCell<Monetary>,
-
-    /// Held funds
-    held: RefCell<Monetary>,
+    /// Funds per asset
+    balances: RefCell<BTreeMap<String, Balance>>,
     
-    /// Transactions storage
-    transactions: RefCell<HashMap<u32, Transaction>>,
+    /// Transactions storage, limited by the retention policy
+    transactions: RefCell<TransactionStore>,
+
+    /// Recent withdrawals and outgoing transfers (asset, amount, time), kept only for the rolling limit
+    recent_withdrawals: RefCell<VecDeque<(String, Monetary, u64)>>,
+
+    /// Disputes opened for the account
+    disputes: RefCell<u32>,
+
+    /// History of the balance changes
+    ledger: RefCell<Ledger>,
+
+    /// Double-entry journal, every balance change is posted into it
+    journal: RefCell<Journal>,
+
+    /// Retention policy of the transactions, the history and the journal
+    retention: Arc<Retention>,
+
+    /// Decimals and rounding per asset
+    precision: Arc<Precision>,
 }
 
+/// One row per asset, with the asset column if the account has funds in any asset but the default one
 impl fmt::Display for Account {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "{}, {:.4}, {:.4}, {:.4}, {}",
-            self.get_id(),
-            self.available_amount(),
-            self.held_amount(),
-            self.total_amount(),
-            self.is_locked(),
-        )?;
+        write!(f, "{}", self.rows(self.has_assets()))
+    }
+}
+
+/// Rows of an account, with the asset column or without it
+pub struct Rows<'a> {
+    account: &'a Account,
+    with_asset: bool,
+}
+
+impl fmt::Display for Rows<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let account = self.account;
+        for (asset, balance) in account.balances.borrow().iter() {
+            let precision = account.precision.for_asset(asset);
+            write!(f, "{}, ", account.get_id())?;
+            if self.with_asset {
+                write!(f, "{}, ", asset)?;
+            }
+            writeln!(f, "{}, {}, {}, {}",
+                precision.format(balance.available),
+                precision.format(balance.held),
+                precision.format(balance.total()),
+                account.is_locked(),
+            )?;
+        }
 
         Ok(())
     }
@@ -59,20 +113,54 @@ impl fmt::Display for Account {
 
 impl Account {
 
-    /// Basic constructor
+    /// Basic constructor. The funds are of the default asset, posted as the opening entries
     pub fn new(id: u16, locked: bool, available: Monetary, held: Monetary) -> Self {
+        let account = Self::empty(id);
+        *account.locked.borrow_mut() = locked;
+
+        account.post(DEFAULT_ASSET, Book::Cash, Book::Available, available);
+        account.post(DEFAULT_ASSET, Book::HeldSuspense, Book::Held, held);
+
+        account
+    }
+
+    /// Constructor for empty accounts. Typically new ones. Has no balances until the first deposit
+    pub fn empty(id: u16) -> Self {
         Self {
             id,
-            locked: RefCell::new(locked),
-            available: RefCell::new(available),
-            held: RefCell::new(held),
-            transactions: RefCell::new(HashMap::new()),
+            locked: RefCell::new(false),
+            balances: RefCell::new(BTreeMap::new()),
+            transactions: RefCell::new(TransactionStore::new(id, Arc::default())),
+            recent_withdrawals: RefCell::new(VecDeque::new()),
+            disputes: RefCell::new(0),
+            ledger: RefCell::new(Ledger::default()),
+            journal: RefCell::new(Journal::default()),
+            retention: Arc::default(),
+            precision: Arc::new(Precision::default()),
         }
     }
 
-    /// Constructor for empty accounts. Typically new ones
-    pub fn empty(id: u16) -> Self {
-        Self::new(id, false, ZERO_MONEY, ZERO_MONEY)
+    /// Rows of the output, one per asset. Without the asset column only the default asset is expected
+    pub fn rows(&self, with_asset: bool) -> Rows<'_> {
+        Rows { account: self, with_asset }
+    }
+
+    /// If the account has funds in any asset but the default one
+    pub fn has_assets(&self) -> bool {
+        self.balances.borrow().keys().any(|asset| asset != DEFAULT_ASSET)
+    }
+
+    /// Sets the precision of the assets, shared with the other accounts
+    pub fn with_precision(mut self, precision: Arc<Precision>) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Sets the retention policy of the stored transactions, the history and the journal. Should be set before any transaction is stored
+    pub fn with_retention(mut self, retention: Arc<Retention>) -> Self {
+        self.transactions = RefCell::new(TransactionStore::new(self.id, retention.clone()));
+        self.retention = retention;
+        self
     }
 
     /// Account (client) id getter
@@ -82,7 +170,7 @@ impl Account {
 
     /// Checks if a transactions with id `tx` exists in the account
     fn transaction_exists(&self, tx: &u32) -> bool {
-        self.transactions.borrow().contains_key(tx)
+        self.transactions.borrow().contains(*tx)
     }
 
     /// Opposite to `transaction_exists`
@@ -90,28 +178,74 @@ impl Account {
         !self.transaction_exists(tx)
     }
 
+    /// Balance of the asset getter. Zero, if the account never had the asset
+    pub fn balance(&self, asset: &str) -> Balance {
+        self.balances.borrow().get(asset).copied().unwrap_or_default()
+    }
+
+    /// Records a balance change into the history
+    pub fn record_change(&self, entry: LedgerEntry) {
+        self.ledger.borrow_mut().record(entry)
+    }
+
+    /// History of the balance changes, in the order they were applied
+    pub fn history(&self) -> Vec<LedgerEntry> {
+        self.ledger.borrow().entries().iter().cloned().collect()
+    }
+
+    /// Drops the history out of the retention and folds the journal, keeping as many postings as transactions.
+    /// Nothing is dropped without a retention policy
+    pub fn compact(&self) {
+        if self.retention.is_empty() {
+            return
+        }
+
+        self.ledger.borrow_mut().compact(&self.retention);
+        self.journal.borrow_mut().compact(self.retention.max_transactions.unwrap_or(0));
+    }
+
+    /// Balances per asset at the point of the history. `None` if the point is not in the history
+    pub fn balances_as_of(&self, point: AsOf) -> Option<BTreeMap<String, Balance>> {
+        self.ledger.borrow().balances_as_of(point)
+    }
+
+    /// Adds the journal of the account to the trial balance
+    pub fn add_to_trial_balance(&self, tb: &mut TrialBalance) {
+        tb.add_account(self.id, &self.journal.borrow(), &self.balances.borrow());
+    }
+
+    /// Transactions kept in memory to be disputed
+    pub fn stored_transactions(&self) -> usize {
+        self.transactions.borrow().len()
+    }
+
+    /// Assets the account has balances in
+    pub fn assets(&self) -> Vec<String> {
+        self.balances.borrow().keys().cloned().collect()
+    }
+
     /// Held amount getter
-    fn held_amount(&self) -> Monetary {
-        *self.held.borrow()
+    fn held_amount(&self, asset: &str) -> Monetary {
+        self.balance(asset).held
     }
 
     /// Available amount getter
-    fn available_amount(&self) -> Monetary {
-        *self.available.borrow()
+    fn available_amount(&self, asset: &str) -> Monetary {
+        self.balance(asset).available
     }
 
     /// Total amount getter
-    fn total_amount(&self) -> Monetary {
-        self.available_amount() + self.held_amount()
+    fn total_amount(&self, asset: &str) -> Monetary {
+        self.balance(asset).total()
     }
 
     /// Returns `true` is the account is locked
-    fn is_locked(&self) -> bool {
+    pub fn is_locked(&self) -> bool {
         *self.locked.borrow()
     }
 
     /// Locks the account
-    fn lock(&self) {
+    pub fn lock(&self) {
         *self.locked.borrow_mut() = true
     }
 
@@ -122,57 +256,57 @@ impl Account {
 
     /// Adds a transaction to the account. Doesn't perform any checks, left it to higher level functions
     fn add_transaction(&self, t: Transaction) {
-        self.transactions.borrow_mut().insert(t.tx(), t);
-    }
-
-    /// Adds money to held
-    fn add_held(&self, amount: Monetary) {
-        *self.held.borrow_mut() += amount
+        self.transactions.borrow_mut().insert(t);
     }
 
-    /// Subs money from held
-    fn sub_held(&self, amount: Monetary) {
-        *self.held.borrow_mut() -= amount
+    /// Applies a change to the balance of the asset, creating it if necessary
+    fn change_balance<F>(&self, asset: &str, f: F)
+    where F: FnOnce(&mut Balance) {
+        let mut balances = self.balances.borrow_mut();
+        f(balances.entry(asset.to_string()).or_default())
     }
 
-    /// Adds money to available
-    fn add_available(&self, amount: Monetary) {
-        *self.available.borrow_mut() += amount
+    /// Posts the entry into the journal and applies it to the balance. The only place the balances change
+    fn post(&self, asset: &str, debit: Book, credit: Book, amount: Monetary) {
+        let posting = Posting { asset: asset.to_string(), debit, credit, amount };
+        self.change_balance(asset, |b| posting.apply(b));
+        self.journal.borrow_mut().push(posting);
     }
 
-    /// Subs money from available
-    fn sub_available(&self, amount: Monetary) {
-        *self.available.borrow_mut() -= amount
+    /// Moves some amount of money from available to held, parking the cash in the suspense
+    fn move_available_2_held(&self, asset: &str, amount: Monetary) {
+        self.post(asset, Book::Available, Book::Held, amount);
+        self.post(asset, Book::HeldSuspense, Book::Cash, amount);
     }
 
-    /// Moves some amount of money from available to held
-    fn move_available_2_held(&self, amount: Monetary) {
-        self.sub_available(amount);
-        self.add_held(amount);
+    /// Moves some amount of money from held to available, back from the suspense
+    fn move_held_2_available(&self, asset: &str, amount: Monetary) {
+        self.post(asset, Book::Held, Book::Available, amount);
+        self.post(asset, Book::Cash, Book::HeldSuspense, amount);
     }
 
-    /// Moves some amount of money from held to available
-    fn move_held_2_available(&self, amount: Monetary) {
-        self.sub_held(amount);
-        self.add_available(amount);
+    /// Moves some amount of money from held to another client, through the clearing
+    fn move_held_2_clearing(&self, asset: &str, amount: Monetary) {
+        self.post(asset, Book::Held, Book::Clearing, amount);
+        self.post(asset, Book::Cash, Book::HeldSuspense, amount);
     }
 
     /// Used to check for an overflow. For instance, when anyone wants to deposit some money,
     /// it would be great to not overflow.
-    /// Not Decimal::MAX, but Decimal::MAX / 10^4, because even though we won't get an overflow immediately,
+    /// Not Decimal::MAX, but Decimal::MAX / 10^decimals, because even though we won't get an overflow immediately,
     /// still the digits after floating point will be eaten.
-    /// As we want to support 4 digits after the point, we need at least 10^4.
-    pub fn amount_till_overflow(&self) -> Monetary {
-        REALLY_REALLY_REALLY_REALLY_A_LOOOOOT - self.total_amount()
+    /// The decimals are the ones of the asset.
+    pub fn amount_till_overflow(&self, asset: &str) -> Monetary {
+        self.precision.for_asset(asset).max_total() - self.total_amount(asset)
     }
 
     /// Tests if possible to deposit this amount of money.
     /// Typically not negative, and `total` should be ready to receive the amount, without overflow.
-    fn test_deposit(&self, amount: Monetary) -> Result<(), AccountError> {
+    fn test_deposit(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
         if amount < ZERO_MONEY {
             return Err(AccountError::NegativeAmount)
         } 
-        let allowed_amount = self.amount_till_overflow();
+        let allowed_amount = self.amount_till_overflow(asset);
         if amount > allowed_amount {
             Err(AccountError::TooMuch(allowed_amount))
         } else {
@@ -182,12 +316,12 @@ impl Account {
 
     /// Tests if possible to take this amount of money from available.
     /// Typically not negative, and available should have the amount.
-    fn test_available(&self, amount: Monetary) -> Result<(), AccountError> {
+    fn test_available(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
         if amount < ZERO_MONEY {
             return Err(AccountError::NegativeAmount)
         }
 
-        let allowed_amount = self.available_amount();
+        let allowed_amount = self.available_amount(asset);
         if amount > allowed_amount {
             Err(AccountError::TooMuch(allowed_amount))
         } else {
@@ -198,11 +332,11 @@ impl Account {
 
     /// Tests if possible to take this amount of money from held.
     /// Typically not negative, and held should have the amount.
-    fn test_held(&self, amount: Monetary) -> Result<(), AccountError> {
+    fn test_held(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
         if amount < ZERO_MONEY {
             return Err(AccountError::NegativeAmount)
         }
-        let allowed_amount = self.held_amount();
+        let allowed_amount = self.held_amount(asset);
         if amount > allowed_amount {
             Err(AccountError::TooMuch(allowed_amount))
         } else {
@@ -211,28 +345,46 @@ impl Account {
     }
 
     /// Deposits the amount to `available`, if possible. Performs necessary monetary checks
-    pub fn deposit(&self, amount: Monetary) -> Result<(), AccountError> {
+    pub fn deposit(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
 
-        self.test_deposit(amount)?;
-        self.add_available(amount);
+        self.test_deposit(asset, amount)?;
+        self.post(asset, Book::Cash, Book::Available, amount);
 
         Ok(())
     }
 
     /// Withdraws the amount from `available`, if possible. Performs necessary monetary checks
-    pub fn withdrawal(&self, amount: Monetary) -> Result<(), AccountError> {
+    pub fn withdrawal(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
 
-        self.test_available(amount)?;
-        self.sub_available(amount);
+        self.test_available(asset, amount)?;
+        self.post(asset, Book::Available, Book::Cash, amount);
+
+        Ok(())
+    }
+
+    /// Receives the amount from another client to `available`, if possible. Performs necessary monetary checks
+    pub fn receive(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
+
+        self.test_deposit(asset, amount)?;
+        self.post(asset, Book::Clearing, Book::Available, amount);
+
+        Ok(())
+    }
+
+    /// Pays the amount from `available` to another client, if possible. Performs necessary monetary checks
+    pub fn pay(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
+
+        self.test_available(asset, amount)?;
+        self.post(asset, Book::Available, Book::Clearing, amount);
 
         Ok(())
     }
 
     /// Moves the amount from `available` to `held`, if possible. Performs necessary monetary checks
-    pub fn dispute(&self, amount: Monetary) -> Result<(), AccountError> {
+    pub fn dispute(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
 
-        self.test_available(amount)?;
-        self.move_available_2_held(amount);
+        self.test_available(asset, amount)?;
+        self.move_available_2_held(asset, amount);
 
         Ok(())
     }
@@ -240,49 +392,54 @@ impl Account {
 
 
     /// Moves the amount from `held` to `available`, if possible. Performs necessary monetary checks
-    pub fn resolve(&self, amount: Monetary) -> Result<(), AccountError> {
+    pub fn resolve(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
 
-        self.test_held(amount)?;
-        self.move_held_2_available(amount);
+        self.test_held(asset, amount)?;
+        self.move_held_2_available(asset, amount);
 
         Ok(())
     }
 
     /// Chargebacks the amount from `held`, if possible, and locks the account. Performs necessary monetary checks
-    pub fn chargeback(&self, amount: Monetary) -> Result<(), AccountError> {
+    pub fn chargeback(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
         
-        self.test_held(amount)?;
-        self.sub_held(amount);
+        self.test_held(asset, amount)?;
+        self.post(asset, Book::Held, Book::ChargebackLoss, amount);
 
         self.lock();
 
         Ok(())
     }
 
-
     /// Just to show a closure to the reviewer... I know, less readable, but yet just to have less boring code.
     /// Basically, gets the transaction and passes it to a closure, which performs necessary actions on it.
+    /// The dispute state is stored back if the closure succeeds.
     /// Saves space for try_dispute, try_resolve, try_chargeback functions
     fn try_perform_with_transaction<F>(&self, tx: u32, f: F) -> Result<(), AccountError> 
     where F: FnOnce(&mut Transaction) -> Result<(), AccountError>  {
-        let mut self_transactions = self.transactions.borrow_mut();
-        let transaction = self_transactions.get_mut(&tx).ok_or(AccountError::TransactionNotFound)?;
+        let mut transaction = self.transactions.borrow_mut().load(tx)?;
+
+        f(&mut transaction)?;
+        self.transactions.borrow_mut().set_disputed(tx, transaction.is_subject_of_dispute());
 
-        f(transaction)
+        Ok(())
     }
 
-    /// Tries to perform a dispute operation against an existing transaction 
+    /// Tries to perform a dispute operation against an existing transaction, in the asset of the existing transaction
     pub fn try_dispute(&self, t: Transaction) -> Result<(), AccountError> {
 
         self.try_perform_with_transaction(t.tx(), 
             |transaction| {
+                if transaction.get_type() == &TransactionType::Transfer {
+                    return Err(AccountError::TransferNeedsCounterparty)
+                }
                 if transaction.is_subject_of_dispute() {
                     return Err(AccountError::TransactionIsSubjectOfDispute)
                 }
         
                 let amount = transaction.amount().ok_or(AccountError::TransactionIsEmpty)?;
 
-                self.dispute(amount)?;
+                self.dispute(transaction.asset(), amount)?;
                 transaction.start_dispute();
                 
                 Ok(())
@@ -295,13 +452,16 @@ impl Account {
 
         self.try_perform_with_transaction(t.tx(), 
             |transaction| {
+                if transaction.get_type() == &TransactionType::Transfer {
+                    return Err(AccountError::TransferNeedsCounterparty)
+                }
                 if transaction.is_not_subject_of_dispute() {
                     return Err(AccountError::TransactionIsNotSubjectOfDispute)
                 }
 
                 let amount = transaction.amount().ok_or(AccountError::TransactionIsEmpty)?;
 
-                self.resolve(amount)?;
+                self.resolve(transaction.asset(), amount)?;
                 transaction.stop_dispute();
                 
                 Ok(())  
@@ -314,13 +474,16 @@ impl Account {
 
         self.try_perform_with_transaction(t.tx(), 
             |transaction| {
+                if transaction.get_type() == &TransactionType::Transfer {
+                    return Err(AccountError::TransferNeedsCounterparty)
+                }
                 if transaction.is_not_subject_of_dispute() {
                     return Err(AccountError::TransactionIsNotSubjectOfDispute)
                 }
 
                 let amount = transaction.amount().ok_or(AccountError::TransactionIsEmpty)?;
 
-                self.chargeback(amount)?;
+                self.chargeback(transaction.asset(), amount)?;
                 transaction.stop_dispute();
                 
                 Ok(())  
@@ -338,7 +501,7 @@ impl Account {
         
         let amount = t.amount().ok_or(AccountError::TransactionIsEmpty)?;
 
-        self.deposit(amount)?;
+        self.deposit(t.asset(), amount)?;
         self.add_transaction(t);
 
         Ok(())
@@ -353,12 +516,202 @@ impl Account {
 
         let amount = t.amount().ok_or(AccountError::TransactionIsEmpty)?;
 
-        self.withdrawal(amount)?;
+        self.withdrawal(t.asset(), amount)?;
         self.add_transaction(t);
         Ok(())
 
     }
 
+    /// Remembers the state before an operation, with the stored transaction `tx` if it's the account's own
+    pub fn checkpoint(&self, tx: Option<u32>) -> Checkpoint {
+        Checkpoint {
+            locked: self.is_locked(),
+            balances: self.balances.borrow().clone(),
+            journal: self.journal.borrow().len(),
+            transaction: tx.map(|tx| self.transactions.borrow().save(tx)),
+        }
+    }
+
+    /// Rolls back to the checkpoint
+    pub fn restore(&self, checkpoint: Checkpoint) {
+        *self.locked.borrow_mut() = checkpoint.locked;
+        *self.balances.borrow_mut() = checkpoint.balances;
+        self.journal.borrow_mut().truncate(checkpoint.journal);
+
+        if let Some(saved) = checkpoint.transaction {
+            self.transactions.borrow_mut().restore(saved);
+        }
+    }
+
+    /// Remembers the fee charged for a stored transaction, so it could be reversed later
+    pub fn set_transaction_fee(&self, tx: u32, fee: Monetary) {
+        self.transactions.borrow_mut().set_fee(tx, fee);
+    }
+
+    /// Takes the amount from `held` to another client, if possible, without locking the account
+    pub fn take_held(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
+
+        self.test_held(asset, amount)?;
+        self.move_held_2_clearing(asset, amount);
+
+        Ok(())
+    }
+
+    /// Checks the risk limits before the transaction is executed at `time`.
+    /// Withdrawals and outgoing transfers are checked for the amount and the balance, disputes for their count
+    pub fn test_limits(&self, t: &Transaction, time: u64, limit: &Limit) -> Result<(), AccountError> {
+        match t.get_type() {
+            TransactionType::Withdrawal | TransactionType::Transfer => {
+                let amount = match t.amount() {
+                    Some(amount) => amount,
+                    None => return Ok(()),
+                };
+
+                if let Some(max) = limit.max_withdrawal {
+                    if amount > max {
+                        return Err(AccountError::WithdrawalLimit(max))
+                    }
+                }
+
+                if let Some(rolling) = &limit.rolling_withdrawals {
+                    let recent: Monetary = self.recent_withdrawals.borrow().iter()
+                        .filter(|(asset, _, at)| asset == t.asset() && rolling.contains(*at, time))
+                        .map(|(_, amount, _)| *amount)
+                        .sum();
+                    if recent + amount > rolling.max_volume {
+                        return Err(AccountError::WithdrawalVolumeLimit((rolling.max_volume - recent).max(ZERO_MONEY)))
+                    }
+                }
+
+                if let Some(minimum) = limit.min_balance {
+                    if self.available_amount(t.asset()) - amount < minimum {
+                        return Err(AccountError::MinimumBalance(minimum))
+                    }
+                }
+            },
+            TransactionType::Dispute => {
+                if let Some(max) = limit.max_disputes {
+                    if *self.disputes.borrow() >= max {
+                        return Err(AccountError::DisputesLimit(max))
+                    }
+                }
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    /// Remembers a successfully executed transaction for the limits. The withdrawals out of the window are forgotten
+    pub fn record_limits(&self, t_type: &TransactionType, asset: &str, amount: Option<Monetary>, time: u64, limit: &Limit) {
+        match t_type {
+            TransactionType::Withdrawal | TransactionType::Transfer => {
+                if let (Some(rolling), Some(amount)) = (&limit.rolling_withdrawals, amount) {
+                    let mut recent = self.recent_withdrawals.borrow_mut();
+                    recent.retain(|(_, _, at)| rolling.contains(*at, time));
+                    recent.push_back((asset.to_string(), amount, time));
+                }
+            },
+            TransactionType::Dispute => {
+                *self.disputes.borrow_mut() += 1;
+            },
+            _ => {},
+        }
+    }
+
+    /// Stored deposit, withdrawal or transfer getter
+    pub fn get_transaction(&self, tx: u32) -> Option<Transaction> {
+        self.transactions.borrow().get(tx).ok()
+    }
+
+    /// Tries to send a transfer: withdraws the amount and keeps the transaction, so it could be disputed later.
+    /// The counterparty is credited by `Db`, which calls `revert_transfer_out` if that fails
+    pub fn try_transfer_out(&self, t: Transaction) -> Result<(), AccountError> {
+        if self.is_locked() {
+            return Err(AccountError::AccountLocked)
+        }
+
+        if self.transaction_exists(&t.tx()) {
+            return Err(AccountError::TransactionAlreadyExists)
+        }
+
+        let amount = t.amount().ok_or(AccountError::TransactionIsEmpty)?;
+
+        self.pay(t.asset(), amount)?;
+        self.add_transaction(t);
+        Ok(())
+    }
+
+    /// Reverts `try_transfer_out`: returns the funds and forgets the transaction
+    pub fn revert_transfer_out(&self, tx: u32) {
+        if let Some(t) = self.transactions.borrow_mut().remove(tx) {
+            if let Some(amount) = t.amount() {
+                self.post(t.asset(), Book::Clearing, Book::Available, amount);
+            }
+        }
+    }
+
+    /// Receives a transfer sent by another account
+    pub fn receive_transfer(&self, asset: &str, amount: Monetary) -> Result<(), AccountError> {
+        if self.is_locked() {
+            return Err(AccountError::AccountLocked)
+        }
+
+        self.receive(asset, amount)
+    }
+
+    /// Tries to perform a dispute, resolve or chargeback against a transfer sent by this account, as a single unit.
+    /// The transferred funds are held and released at the `recipient`. Chargeback moves them back to this account and locks it.
+    pub fn try_transfer_dispute(&self, t: Transaction, recipient: &Account) -> Result<(), AccountError> {
+        if self.is_locked() {
+            return Err(AccountError::AccountLocked)
+        }
+
+        let r#type = t.get_type().clone();
+
+        self.try_perform_with_transaction(t.tx(), 
+            |transaction| {
+                let amount = transaction.amount().ok_or(AccountError::TransactionIsEmpty)?;
+                let asset = transaction.asset().to_string();
+
+                match r#type {
+                    TransactionType::Dispute => {
+                        if transaction.is_subject_of_dispute() {
+                            return Err(AccountError::TransactionIsSubjectOfDispute)
+                        }
+
+                        recipient.dispute(&asset, amount)?;
+                        transaction.start_dispute();
+                    },
+                    TransactionType::Resolve => {
+                        if transaction.is_not_subject_of_dispute() {
+                            return Err(AccountError::TransactionIsNotSubjectOfDispute)
+                        }
+
+                        recipient.resolve(&asset, amount)?;
+                        transaction.stop_dispute();
+                    },
+                    TransactionType::Chargeback => {
+                        if transaction.is_not_subject_of_dispute() {
+                            return Err(AccountError::TransactionIsNotSubjectOfDispute)
+                        }
+
+                        recipient.test_held(&asset, amount)?;
+                        self.test_deposit(&asset, amount)?;
+
+                        recipient.move_held_2_clearing(&asset, amount);
+                        self.post(&asset, Book::Clearing, Book::Available, amount);
+                        self.lock();
+                        transaction.stop_dispute();
+                    },
+                    _ => return Err(AccountError::TransferNeedsCounterparty),
+                }
+
+                Ok(())
+            }
+        )
+    }
+
     /// Main entrypoint for a new transaction to an account. Checks types an performs operation.
     /// The only place to check if account is locked. 
     pub fn execute_transaction(&self, t: Transaction) -> Result<(), AccountError> {
@@ -381,7 +734,10 @@ impl Account {
             },
             TransactionType::Chargeback => {
                 self.try_chargeback(t)
-            }
+            },
+            TransactionType::Transfer => {
+                Err(AccountError::TransferNeedsCounterparty)
+            },
         }
     }
 }
@@ -395,52 +751,54 @@ mod tests {
     #[test]
     fn empty_is_empty() {
         let a = Account::empty(1);
-        assert_eq!(a.available_amount(), dec!(0));
-        assert_eq!(a.held_amount(), dec!(0));
-        assert_eq!(a.total_amount(), dec!(0));
+        assert_eq!(a.available_amount(DEFAULT_ASSET), dec!(0));
+        assert_eq!(a.held_amount(DEFAULT_ASSET), dec!(0));
+        assert_eq!(a.total_amount(DEFAULT_ASSET), dec!(0));
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn account_tests() {
         let a = Account::empty(1);
 
         assert_eq!(a.is_locked(), false);
 
-        assert_eq!(a.deposit(dec!(15.0)), Ok(()));
+        assert_eq!(a.deposit(DEFAULT_ASSET, dec!(15.0)), Ok(()));
 
-        assert_eq!(a.available_amount(), dec!(15.0));
+        assert_eq!(a.available_amount(DEFAULT_ASSET), dec!(15.0));
 
-        assert_eq!(a.dispute(dec!(16.0)), Err(AccountError::TooMuch(dec!(15.0))));
+        assert_eq!(a.dispute(DEFAULT_ASSET, dec!(16.0)), Err(AccountError::TooMuch(dec!(15.0))));
 
-        assert_eq!(a.dispute(dec!(7.5)), Ok(()));
+        assert_eq!(a.dispute(DEFAULT_ASSET, dec!(7.5)), Ok(()));
 
-        assert_eq!(a.available_amount(), dec!(7.5));
+        assert_eq!(a.available_amount(DEFAULT_ASSET), dec!(7.5));
 
-        assert_eq!(a.resolve(dec!(7)), Ok(()));
+        assert_eq!(a.resolve(DEFAULT_ASSET, dec!(7)), Ok(()));
 
-        assert_eq!(a.chargeback(dec!(7)), Err(AccountError::TooMuch(dec!(0.5))));
+        assert_eq!(a.chargeback(DEFAULT_ASSET, dec!(7)), Err(AccountError::TooMuch(dec!(0.5))));
 
-        assert_eq!(a.chargeback(dec!(0.5)), Ok(()));
+        assert_eq!(a.chargeback(DEFAULT_ASSET, dec!(0.5)), Ok(()));
 
         assert_eq!(a.is_locked(), true);
 
-        assert_eq!(a.total_amount(), dec!(14.5));
+        assert_eq!(a.total_amount(DEFAULT_ASSET), dec!(14.5));
 
-        assert_eq!(a.withdrawal(dec!(14.5)), Ok(()));
+        assert_eq!(a.withdrawal(DEFAULT_ASSET, dec!(14.5)), Ok(()));
     }
 
     #[test]
     fn wrong_amounts() {
         let a = Account::empty(1);
 
-        assert_eq!(a.deposit(dec!(1.0)), Ok(()));
+        assert_eq!(a.deposit(DEFAULT_ASSET, dec!(1.0)), Ok(()));
 
-        assert_eq!(a.available_amount(), dec!(1.0));
-        assert_eq!(a.withdrawal(dec!(2.0)), Err(AccountError::TooMuch(a.available_amount())));
+        assert_eq!(a.available_amount(DEFAULT_ASSET), dec!(1.0));
+        assert_eq!(a.withdrawal(DEFAULT_ASSET, dec!(2.0)), Err(AccountError::TooMuch(a.available_amount(DEFAULT_ASSET))));
         
-        assert_eq!(a.deposit(dec!(-1.0)), Err(AccountError::NegativeAmount));
+        assert_eq!(a.deposit(DEFAULT_ASSET, dec!(-1.0)), Err(AccountError::NegativeAmount));
 
-        assert_eq!(a.deposit(REALLY_REALLY_REALLY_REALLY_A_LOOOOOT - dec!(0.5)), Err(AccountError::TooMuch(dec!(7922816251426433759354395.0))));
+        let max_total = Precision::default().for_asset(DEFAULT_ASSET).max_total();
+        assert_eq!(a.deposit(DEFAULT_ASSET, max_total - dec!(0.5)), Err(AccountError::TooMuch(max_total - dec!(1.0))));
     }
 
     #[test]
@@ -527,4 +885,34 @@ mod tests {
 
         assert_eq!(a.execute_transaction(t), Err(AccountError::AccountLocked));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn assets_are_separate() {
+        let a = Account::empty(1);
+
+        let t = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10.0)), false).with_asset("EUR");
+        assert_eq!(a.execute_transaction(t), Ok(()));
+
+        let t = Transaction::new(TransactionType::Deposit, 1, 2, Some(dec!(3.0)), false).with_asset("BTC");
+        assert_eq!(a.execute_transaction(t), Ok(()));
+
+        assert_eq!(a.assets(), vec!["BTC".to_string(), "EUR".to_string()]);
+
+        let t = Transaction::new(TransactionType::Withdrawal, 1, 3, Some(dec!(5.0)), false).with_asset("BTC");
+        assert_eq!(a.execute_transaction(t), Err(AccountError::TooMuch(dec!(3.0))));
+
+        // Dispute is scoped to the asset of the original transaction, no matter what the dispute says
+        let t = Transaction::new(TransactionType::Dispute, 1, 1, None, false).with_asset("BTC");
+        assert_eq!(a.execute_transaction(t), Ok(()));
+
+        assert_eq!(a.balance("EUR"), Balance::new(dec!(0.0), dec!(10.0)));
+        assert_eq!(a.balance("BTC"), Balance::new(dec!(3.0), dec!(0)));
+
+        let t = Transaction::new(TransactionType::Resolve, 1, 1, None, false);
+        assert_eq!(a.execute_transaction(t), Ok(()));
+
+        assert_eq!(a.balance("EUR"), Balance::new(dec!(10.0), dec!(0.0)));
+        assert_eq!(a.balance(DEFAULT_ASSET), Balance::default());
+        assert_eq!(format!("{}", a), "1, BTC, 3.0000, 0.0000, 3.0000, false\n1, EUR, 10.0000, 0.0000, 10.0000, false\n");
+    }
+}
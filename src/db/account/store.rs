@@ -0,0 +1,440 @@
+use rustc_hash::FxHashMap;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::db::account::error::AccountError;
+use crate::db::retention::{IdSet, Retention, Spill};
+use crate::db::transaction::{Transaction, TransactionType, DEFAULT_ASSET};
+use crate::Monetary;
+
+
+/// Type of a stored transaction, only these could be disputed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Transfer,
+}
+
+impl Kind {
+
+    fn of(r#type: &TransactionType) -> Option<Self> {
+        match r#type {
+            TransactionType::Deposit => Some(Kind::Deposit),
+            TransactionType::Withdrawal => Some(Kind::Withdrawal),
+            TransactionType::Transfer => Some(Kind::Transfer),
+            _ => None,
+        }
+    }
+
+    fn r#type(self) -> TransactionType {
+        match self {
+            Kind::Deposit => TransactionType::Deposit,
+            Kind::Withdrawal => TransactionType::Withdrawal,
+            Kind::Transfer => TransactionType::Transfer,
+        }
+    }
+}
+
+/// Stored transaction, only what a dispute needs: 24 bytes.
+/// The client is the owner of the store, the tx is the key
+#[derive(Debug, Clone, Copy)]
+struct Stored {
+    amount: Monetary,
+    /// Place in the retention order
+    seq: u32,
+    kind: Kind,
+    disputed: bool,
+}
+
+/// Rarely set parts of a stored transaction, kept aside only if any is set
+#[derive(Debug, Clone, Default)]
+struct Extra {
+    asset: String,
+    counterparty: Option<u16>,
+    fee: Option<Monetary>,
+}
+
+impl Extra {
+
+    fn of(t: &Transaction) -> Self {
+        Self {
+            asset: t.asset().to_string(),
+            counterparty: t.counterparty(),
+            fee: t.fee(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.asset == DEFAULT_ASSET && self.counterparty.is_none() && self.fee.is_none()
+    }
+}
+
+/// Stored transaction as it was before an operation, to roll back to
+#[derive(Debug, Clone)]
+pub struct Saved {
+    tx: u32,
+    transaction: Option<Transaction>,
+    /// The id was known, even if evicted
+    known: bool,
+}
+
+/// Stored deposits, withdrawals and transfers of an account, in a compact layout, evicted by the retention policy
+/// in the arrival order. Transactions under a dispute are never evicted. The evicted ones are spilled to disk if configured,
+/// otherwise only their ids are kept, to tell them apart from the unknown ones
+#[derive(Debug)]
+pub struct TransactionStore {
+    client: u16,
+    retention: Arc<Retention>,
+    live: FxHashMap<u32, Stored>,
+    extras: FxHashMap<u32, Extra>,
+    /// Arrival order with the timestamps, kept only with a retention policy.
+    /// Entries which sequence number doesn't match the live transaction are stale and skipped
+    order: VecDeque<(u32, u32, Option<u64>)>,
+    seq: u32,
+    /// Latest timestamp seen
+    latest: u64,
+    /// Dropped transactions, the latest `max_evicted` of them
+    evicted: IdSet,
+    spill: Option<Spill>,
+}
+
+impl TransactionStore {
+
+    /// Constructor for the account `client`
+    pub fn new(client: u16, retention: Arc<Retention>) -> Self {
+        let spill = retention.spill_dir.as_ref().map(|dir| Spill::new(dir.join(format!("{}.jsonl", client))));
+        let evicted = IdSet::new(retention.max_evicted);
+
+        Self {
+            client,
+            retention,
+            live: FxHashMap::default(),
+            extras: FxHashMap::default(),
+            order: VecDeque::new(),
+            seq: 0,
+            latest: 0,
+            evicted,
+            spill,
+        }
+    }
+
+    /// Amount of transactions in memory
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Returns `true` if there are no transactions in memory
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+
+    /// Returns `true` if the transaction was ever stored, even if it's evicted
+    pub fn contains(&self, tx: u32) -> bool {
+        self.live.contains_key(&tx) || self.evicted.contains(tx) || self.spill.as_ref().is_some_and(|s| s.contains(tx))
+    }
+
+    /// Error for a transaction, which is not in memory
+    fn missing(&self, tx: u32) -> AccountError {
+        if self.contains(tx) {
+            AccountError::TransactionEvicted
+        } else {
+            AccountError::TransactionNotFound
+        }
+    }
+
+    /// Full transaction out of the stored parts
+    fn build(&self, tx: u32, stored: &Stored) -> Transaction {
+        let mut t = Transaction::new(stored.kind.r#type(), self.client, tx, Some(stored.amount), stored.disputed);
+
+        if let Some(extra) = self.extras.get(&tx) {
+            if extra.asset != DEFAULT_ASSET {
+                t = t.with_asset(&extra.asset);
+            }
+            if let Some(counterparty) = extra.counterparty {
+                t = t.with_counterparty(counterparty);
+            }
+            if let Some(fee) = extra.fee {
+                t.set_fee(fee);
+            }
+        }
+
+        t
+    }
+
+    /// Transaction getter, reads the spilled ones from disk
+    pub fn get(&self, tx: u32) -> Result<Transaction, AccountError> {
+        if let Some(stored) = self.live.get(&tx) {
+            return Ok(self.build(tx, stored))
+        }
+
+        self.spill.as_ref().and_then(|s| s.get(tx)).ok_or_else(|| self.missing(tx))
+    }
+
+    /// Transaction getter, loads a spilled transaction back into memory, to be changed
+    pub fn load(&mut self, tx: u32) -> Result<Transaction, AccountError> {
+        if !self.live.contains_key(&tx) {
+            match self.spill.as_mut().and_then(|s| s.take(tx)) {
+                Some(t) => self.push(t),
+                None => return Err(self.missing(tx)),
+            }
+        }
+
+        self.get(tx)
+    }
+
+    /// Sets the dispute state of a transaction in memory
+    pub fn set_disputed(&mut self, tx: u32, disputed: bool) {
+        if let Some(stored) = self.live.get_mut(&tx) {
+            stored.disputed = disputed;
+        }
+    }
+
+    /// Sets the fee charged for a transaction in memory
+    pub fn set_fee(&mut self, tx: u32, fee: Monetary) {
+        if self.live.contains_key(&tx) {
+            self.extras.entry(tx).or_default().fee = Some(fee);
+        }
+    }
+
+    /// Stores a new transaction, evicting the ones out of the retention.
+    /// Only deposits, withdrawals and transfers with an amount are stored
+    pub fn insert(&mut self, t: Transaction) {
+        self.latest = self.latest.max(t.timestamp().unwrap_or_default());
+        self.push(t);
+        self.evict();
+    }
+
+    /// Forgets the transaction completely
+    pub fn remove(&mut self, tx: u32) -> Option<Transaction> {
+        self.evicted.remove(tx);
+        if let Some(spill) = &mut self.spill {
+            spill.remove(tx);
+        }
+
+        let t = self.live.remove(&tx).map(|stored| self.build(tx, &stored));
+        self.extras.remove(&tx);
+        t
+    }
+
+    /// Remembers the transaction before an operation on it
+    pub fn save(&self, tx: u32) -> Saved {
+        Saved {
+            tx,
+            transaction: self.get(tx).ok(),
+            known: self.contains(tx),
+        }
+    }
+
+    /// Puts the transaction back as it was before an operation. Evicted and spilled ones stay as they are,
+    /// unless the transaction was unknown before, then it's forgotten completely
+    pub fn restore(&mut self, saved: Saved) {
+        let tx = saved.tx;
+
+        match saved.transaction {
+            Some(t) => {
+                if !self.contains(tx) {
+                    self.push(t);
+                } else if let (Some(stored), Some(amount)) = (self.live.get_mut(&tx), t.amount()) {
+                    stored.amount = amount;
+                    stored.disputed = t.is_subject_of_dispute();
+                    self.set_extra(&t);
+                }
+            },
+            None if !saved.known => {
+                self.remove(tx);
+            },
+            None => {
+                self.live.remove(&tx);
+                self.extras.remove(&tx);
+            },
+        }
+    }
+
+    fn set_extra(&mut self, t: &Transaction) {
+        let extra = Extra::of(t);
+        if extra.is_empty() {
+            self.extras.remove(&t.tx());
+        } else {
+            self.extras.insert(t.tx(), extra);
+        }
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        self.seq = self.seq.wrapping_add(1);
+        self.seq
+    }
+
+    fn push(&mut self, t: Transaction) {
+        let (kind, amount) = match (Kind::of(t.get_type()), t.amount()) {
+            (Some(kind), Some(amount)) => (kind, amount),
+            _ => return,
+        };
+
+        let seq = self.next_seq();
+        if !self.retention.is_empty() {
+            self.order.push_back((seq, t.tx(), t.timestamp()));
+        }
+
+        self.live.insert(t.tx(), Stored { amount, seq, kind, disputed: t.is_subject_of_dispute() });
+        self.set_extra(&t);
+    }
+
+    /// Returns `true` if the transaction with the timestamp is out of the retention
+    fn is_expired(&self, timestamp: Option<u64>) -> bool {
+        let over = match self.retention.max_transactions {
+            Some(max) => self.live.len() > max,
+            None => false,
+        };
+        let old = match (self.retention.max_age_ms, timestamp) {
+            (Some(age), Some(timestamp)) => self.latest.saturating_sub(timestamp) > age,
+            _ => false,
+        };
+
+        over || old
+    }
+
+    /// Evicts the oldest transactions while they are out of the retention
+    fn evict(&mut self) {
+        // Disputed transactions go to the back, stop when every one left is disputed
+        let mut disputed = 0;
+
+        while let Some(&(seq, tx, timestamp)) = self.order.front() {
+            let stored = match self.live.get(&tx) {
+                Some(stored) if stored.seq == seq => *stored,
+                _ => {
+                    self.order.pop_front();
+                    continue
+                },
+            };
+
+            if !self.is_expired(timestamp) || disputed >= self.live.len() {
+                break
+            }
+
+            self.order.pop_front();
+
+            if stored.disputed {
+                disputed += 1;
+                let seq = self.next_seq();
+                if let Some(stored) = self.live.get_mut(&tx) {
+                    stored.seq = seq;
+                }
+                self.order.push_back((seq, tx, timestamp));
+                continue
+            }
+
+            let mut t = self.build(tx, &stored);
+            if let Some(timestamp) = timestamp {
+                t = t.with_timestamp(timestamp);
+            }
+            self.live.remove(&tx);
+            self.extras.remove(&tx);
+
+            let spilled = match &mut self.spill {
+                Some(spill) => spill.put(&t).is_ok(),
+                None => false,
+            };
+            if !spilled {
+                self.evicted.insert(tx);
+            }
+
+            if let (Some(spill), Some(max)) = (&mut self.spill, self.retention.max_spilled) {
+                while let Some(tx) = spill.drop_oldest(max) {
+                    self.evicted.insert(tx);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn deposit(tx: u32, timestamp: u64) -> Transaction {
+        Transaction::new(TransactionType::Deposit, 1, tx, Some(dec!(1)), false).with_timestamp(timestamp)
+    }
+
+    #[test]
+    fn compact_layout() {
+        assert_eq!(std::mem::size_of::<Stored>(), 24);
+
+        let mut store = TransactionStore::new(1, Arc::new(Retention::default()));
+        store.insert(deposit(1, 0));
+        store.insert(Transaction::new(TransactionType::Transfer, 1, 2, Some(dec!(2)), false).with_counterparty(3).with_asset("EUR"));
+        store.set_fee(1, dec!(0.1));
+        store.set_disputed(1, true);
+        // Not disputable
+        store.insert(Transaction::new(TransactionType::Dispute, 1, 3, None, false));
+
+        let first = store.get(1).unwrap();
+        assert_eq!((first.client(), first.amount(), first.fee()), (1, Some(dec!(1)), Some(dec!(0.1))));
+        assert!(first.is_subject_of_dispute());
+        let second = store.get(2).unwrap();
+        assert_eq!((second.get_type(), second.asset(), second.counterparty()), (&TransactionType::Transfer, "EUR", Some(3)));
+        assert_eq!(store.get(3).unwrap_err(), AccountError::TransactionNotFound);
+        assert_eq!(store.len(), 2);
+        // Without a retention there is no order
+        assert!(store.order.is_empty());
+    }
+
+    #[test]
+    fn evicts_by_count_and_age() {
+        let retention = Retention { max_transactions: Some(2), max_age_ms: Some(100), ..Default::default() };
+        let mut store = TransactionStore::new(1, Arc::new(retention));
+
+        store.insert(deposit(1, 0));
+        store.set_disputed(1, true);
+        store.insert(deposit(2, 10));
+        store.insert(deposit(3, 20));
+        // The disputed one stays
+        assert_eq!(store.len(), 2);
+        assert!(store.get(1).is_ok());
+        assert_eq!(store.get(2).unwrap_err(), AccountError::TransactionEvicted);
+        assert!(store.contains(2));
+        assert_eq!(store.get(4).unwrap_err(), AccountError::TransactionNotFound);
+
+        store.set_disputed(1, false);
+        store.insert(deposit(4, 150));
+        assert_eq!(store.len(), 1);
+        assert!(store.get(4).is_ok());
+    }
+
+    #[test]
+    fn spills_to_disk() {
+        let dir = std::env::temp_dir().join(format!("case-spill-{}", std::process::id()));
+        let retention = Retention { max_transactions: Some(1), spill_dir: Some(dir.clone()), max_spilled: Some(2), ..Default::default() };
+        let mut store = TransactionStore::new(1, Arc::new(retention));
+
+        let mut first = deposit(1, 0).with_asset("EUR");
+        first.set_fee(dec!(0.5));
+        store.insert(first);
+        store.insert(deposit(2, 0));
+        assert_eq!(store.len(), 1);
+
+        let spilled = store.get(1).unwrap();
+        assert_eq!(spilled.asset(), "EUR");
+        assert_eq!(spilled.fee(), Some(dec!(0.5)));
+
+        // Loaded back to be disputed, the other one is spilled at the next insert
+        store.load(1).unwrap();
+        store.set_disputed(1, true);
+        assert_eq!(store.len(), 2);
+        store.insert(deposit(3, 0));
+        assert!(store.get(1).unwrap().is_subject_of_dispute());
+        assert_eq!(store.get(2).unwrap().amount(), Some(dec!(1)));
+
+        // Over the spill limit, only the id of the oldest one is kept
+        store.set_disputed(1, false);
+        store.insert(deposit(4, 0));
+        assert_eq!(store.get(2).unwrap_err(), AccountError::TransactionEvicted);
+        assert!(store.get(1).is_ok() && store.get(3).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+use crate::db::transaction::TransactionType;
+
+
+/// Change of an account by a processed transaction, pushed to the subscriber of the engine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountEvent {
+    pub client: u16,
+    /// Client who made the transaction: another one for the counterparty of a transfer and the house account
+    pub origin: u16,
+    pub tx: u32,
+    pub r#type: TransactionType,
+    pub asset: String,
+    /// Balances after the transaction, with the decimals of the asset
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    /// `ok`, or the error the transaction was rejected with
+    pub outcome: String,
+}
+
+/// Receives the events of the engine. Called under the engine, so it should be quick
+pub type EventSink = Box<dyn Fn(AccountEvent) + Send>;
@@ -0,0 +1,123 @@
+use serde::Deserialize;
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use std::collections::HashMap;
+
+use crate::db::transaction::TransactionType;
+use crate::db::precision::AssetPrecision;
+use crate::Monetary;
+
+
+/// Fee of a single transaction type: `flat + amount * percentage / 100`, limited by `min` and `max`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Fee {
+    pub flat: Monetary,
+    pub percentage: Monetary,
+    pub min: Option<Monetary>,
+    pub max: Option<Monetary>,
+}
+
+impl Fee {
+
+    /// Calculates the fee for the amount of the transaction, rounded to the precision of the asset. Never negative.
+    /// If the calculation overflows, the fee is as big as possible, so nobody could pay it
+    pub fn amount(&self, base: Monetary, precision: &AssetPrecision) -> Monetary {
+        let mut fee = base.abs()
+            .checked_mul(self.percentage)
+            .and_then(|x| x.checked_div(dec!(100)))
+            .and_then(|x| x.checked_add(self.flat))
+            .unwrap_or(Decimal::MAX);
+
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+
+        precision.round(fee.max(Decimal::ZERO))
+    }
+}
+
+/// Fees per transaction type and the account they go to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FeeSchedule {
+    /// Client id of the house account, which receives the fees
+    pub house_account: u16,
+    pub fees: HashMap<TransactionType, Fee>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            house_account: u16::MAX,
+            fees: HashMap::new(),
+        }
+    }
+}
+
+impl FeeSchedule {
+
+    /// Returns `true` if no fees are configured
+    pub fn is_empty(&self) -> bool {
+        self.fees.is_empty()
+    }
+
+    /// Fee for a transaction of the type with the amount, if there should be any
+    pub fn fee(&self, r#type: &TransactionType, amount: Monetary, precision: &AssetPrecision) -> Option<Monetary> {
+        self.fees.get(r#type)
+            .map(|fee| fee.amount(amount, precision))
+            .filter(|fee| !fee.is_zero())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::precision::Rounding;
+
+    #[test]
+    fn fee_amount() {
+        let precision = AssetPrecision::default();
+        let fee = Fee { flat: dec!(1), percentage: dec!(2.5), min: None, max: None };
+        assert_eq!(fee.amount(dec!(100), &precision), dec!(3.5));
+
+        let fee = Fee { flat: dec!(0), percentage: dec!(1), min: Some(dec!(2)), max: Some(dec!(5)) };
+        assert_eq!(fee.amount(dec!(10), &precision), dec!(2));
+        assert_eq!(fee.amount(dec!(300), &precision), dec!(3));
+        assert_eq!(fee.amount(dec!(10000), &precision), dec!(5));
+
+        let fee = Fee { flat: dec!(0), percentage: dec!(0.33333), min: None, max: None };
+        assert_eq!(fee.amount(dec!(1), &precision), dec!(0.0033));
+
+        let fee = Fee { flat: dec!(1), percentage: dec!(100), min: None, max: None };
+        assert_eq!(fee.amount(Decimal::MAX, &precision), Decimal::MAX);
+
+        let fee = Fee { flat: dec!(0), percentage: dec!(0.5), min: None, max: None };
+        let cents = AssetPrecision { decimals: 2, rounding: Rounding::HalfUp };
+        assert_eq!(fee.amount(dec!(1), &cents), dec!(0.01));
+    }
+
+    #[test]
+    fn schedule_from_json() {
+        let precision = AssetPrecision::default();
+        let schedule: FeeSchedule = serde_json::from_str(r#"{
+            "house_account": 0,
+            "fees": {
+                "withdrawal": {"flat": 0.5},
+                "chargeback": {"percentage": 10, "max": 25}
+            }
+        }"#).unwrap();
+
+        assert_eq!(schedule.house_account, 0);
+        assert_eq!(schedule.fee(&TransactionType::Withdrawal, dec!(100), &precision), Some(dec!(0.5)));
+        assert_eq!(schedule.fee(&TransactionType::Chargeback, dec!(1000), &precision), Some(dec!(25)));
+        assert_eq!(schedule.fee(&TransactionType::Deposit, dec!(100), &precision), None);
+    }
+}
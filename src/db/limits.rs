@@ -0,0 +1,108 @@
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Monetary;
+
+
+/// Limits the volume of the withdrawals (and outgoing transfers) made within `window_ms` milliseconds
+/// before the new one, including it. Time is the timestamp of a transaction, the time of the `Clock` of the engine without one
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct RollingWithdrawals {
+    pub window_ms: u64,
+    pub max_volume: Monetary,
+}
+
+impl RollingWithdrawals {
+
+    /// Returns `true` if a withdrawal made at `time` is in the window of the one made at `now`.
+    /// Later ones are in it as well, the input could be out of order
+    pub fn contains(&self, time: u64, now: u64) -> bool {
+        time.saturating_add(self.window_ms) > now
+    }
+}
+
+/// Risk limits of an account. Every limit is optional.
+/// Amounts are compared in the asset of the transaction
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Limit {
+    /// Maximum amount of a single withdrawal or outgoing transfer
+    pub max_withdrawal: Option<Monetary>,
+    /// Maximum volume of the recent withdrawals
+    pub rolling_withdrawals: Option<RollingWithdrawals>,
+    /// Maximum amount of disputes opened for the account
+    pub max_disputes: Option<u32>,
+    /// Available funds which must stay after a withdrawal or an outgoing transfer
+    pub min_balance: Option<Monetary>,
+}
+
+impl Limit {
+
+    /// Returns `true` if nothing is limited
+    pub fn is_empty(&self) -> bool {
+        self == &Limit::default()
+    }
+
+    /// Limits of `self`, with the missing ones taken from `other`
+    pub fn or(&self, other: &Limit) -> Limit {
+        Limit {
+            max_withdrawal: self.max_withdrawal.or(other.max_withdrawal),
+            rolling_withdrawals: self.rolling_withdrawals.clone().or_else(|| other.rolling_withdrawals.clone()),
+            max_disputes: self.max_disputes.or(other.max_disputes),
+            min_balance: self.min_balance.or(other.min_balance),
+        }
+    }
+}
+
+/// Global limits, and the per-client ones overriding them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub global: Limit,
+    pub clients: HashMap<u16, Limit>,
+}
+
+impl Limits {
+
+    /// Returns `true` if no limits are configured
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.clients.values().all(|limit| limit.is_empty())
+    }
+
+    /// Limits of the client: the client's own ones, the global ones otherwise
+    pub fn for_client(&self, client: u16) -> Limit {
+        match self.clients.get(&client) {
+            Some(limit) => limit.or(&self.global),
+            None => self.global.clone(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn client_overrides_global() {
+        let limits: Limits = serde_json::from_str(r#"{
+            "global": {"max_withdrawal": 100, "max_disputes": 3},
+            "clients": {"7": {"max_withdrawal": 5000, "min_balance": 10}}
+        }"#).unwrap();
+
+        assert!(!limits.is_empty());
+
+        let limit = limits.for_client(7);
+        assert_eq!(limit.max_withdrawal, Some(dec!(5000)));
+        assert_eq!(limit.max_disputes, Some(3));
+        assert_eq!(limit.min_balance, Some(dec!(10)));
+
+        assert_eq!(limits.for_client(1).max_withdrawal, Some(dec!(100)));
+        assert_eq!(limits.for_client(1).min_balance, None);
+
+        assert!(Limits::default().is_empty());
+    }
+}
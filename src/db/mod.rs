@@ -1,17 +1,59 @@
 pub mod account;
 pub mod transaction;
+pub mod fees;
+pub mod limits;
+pub mod rules;
+pub mod precision;
+pub mod retention;
+pub mod events;
 
-use account::{Account, error::AccountError};
-use transaction::{Transaction, TransactionType};
+use account::{Account, Checkpoint, error::AccountError};
+use account::balance::Balance;
+use account::ledger::{AsOf, LedgerEntry};
+use account::journal::TrialBalance;
+use transaction::{now_ms, Clock, Transaction, TransactionType};
+use fees::FeeSchedule;
+use limits::Limits;
+use rules::{Activity, Monitor};
+use precision::Precision;
+use retention::Retention;
+use events::{AccountEvent, EventSink};
+
+use crate::config::Config;
+use crate::Monetary;
 
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 
 #[derive(Debug, Clone)]
 pub enum DBError {
     AccountError(AccountError),
     AccountNotFound,
+    /// Transfer without the counterparty column
+    CounterpartyNotSet,
+    /// Transfer to the same client
+    TransferToItself,
+    /// Amount has more decimals than the asset allows, and the asset rejects such
+    TooPrecise(u32),
+    /// Client or counterparty is the house account, which receives the fees
+    HouseAccount,
+}
+
+impl DBError {
+
+    /// Name of the variant, of the account error for those, as a metrics label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DBError::AccountError(e) => e.kind(),
+            DBError::AccountNotFound => "account_not_found",
+            DBError::CounterpartyNotSet => "counterparty_not_set",
+            DBError::TransferToItself => "transfer_to_itself",
+            DBError::TooPrecise(_) => "too_precise",
+            DBError::HouseAccount => "house_account",
+        }
+    }
 }
 
 impl fmt::Display for DBError {
@@ -24,6 +66,18 @@ impl fmt::Display for DBError {
             DBError::AccountNotFound => {
                 write!(f, "Account not found")
             },
+            DBError::CounterpartyNotSet => {
+                write!(f, "Transfer requires a counterparty")
+            },
+            DBError::TransferToItself => {
+                write!(f, "Transfer to the same client")
+            },
+            DBError::TooPrecise(decimals) => {
+                write!(f, "Amount has more than {} decimals", decimals)
+            },
+            DBError::HouseAccount => {
+                write!(f, "Client id is reserved for the house account")
+            },
         }
         
     }
@@ -35,13 +89,84 @@ impl From<AccountError> for DBError {
     }
 }
 
+/// Sizes of the engine
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbStats {
+    pub accounts: usize,
+    /// Transactions kept in memory to be disputed
+    pub transactions: usize,
+    pub locked: usize,
+    /// Held funds of all the accounts per asset
+    pub held: BTreeMap<String, Monetary>,
+}
+
+/// Balances a transaction could change, remembered before it to record the history after
+struct PendingChange {
+    client: u16,
+    tx: u32,
+    r#type: TransactionType,
+    asset: String,
+    timestamp: Option<u64>,
+    /// Event time: the timestamp, or the time of the clock
+    time: u64,
+    /// Balance and the lock of every touched account
+    before: Vec<(u16, Balance, bool)>,
+    /// Balances of the touched accounts after the transaction itself, before its fees. Empty without fees
+    before_fees: Vec<(u16, Balance)>,
+}
+
 #[derive(Default)]
 pub struct Db {
     accounts: HashMap<u16, Account>,
+    fees: FeeSchedule,
+    limits: Limits,
+    monitor: Monitor,
+    precision: Arc<Precision>,
+    retention: Arc<Retention>,
+    /// Gets an event for every account change, if set
+    events: Option<EventSink>,
+    clock: Clock,
+    /// Latest timestamp seen
+    latest: u64,
 }
 
 impl Db {
 
+    /// Constructor for the configured engine
+    pub fn new(config: &Config) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            fees: config.fees.clone(),
+            limits: config.limits.clone(),
+            monitor: Monitor::new(&config.rules),
+            precision: Arc::new(config.precision.clone()),
+            retention: Arc::new(config.retention.clone()),
+            events: None,
+            clock: Clock::default(),
+            latest: 0,
+        }
+    }
+
+    /// Sets the time of the transactions without a timestamp, the arrival time by default
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Pushes an event into the sink for every account a transaction changes or locks,
+    /// and for the client of every rejected transaction
+    pub fn with_events(mut self, sink: impl Fn(AccountEvent) + Send + 'static) -> Self {
+        self.events = Some(Box::new(sink));
+        self
+    }
+
+    /// New empty account, with the configured precision and retention
+    fn new_account(&self, id: u16) -> Account {
+        Account::empty(id)
+            .with_precision(self.precision.clone())
+            .with_retention(self.retention.clone())
+    }
+
     fn add_account(&mut self, account: Account) {
         let id = account.get_id();
         self.accounts.insert(id, account);
@@ -51,13 +176,380 @@ impl Db {
         self.accounts.get_mut(id)
     }
 
+    /// Account getter
+    pub fn get_account(&self, id: u16) -> Option<&Account> {
+        self.accounts.get(&id)
+    }
+
+    /// Fraud monitor with the raised flags
+    pub fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+
+    /// Decimals and rounding per asset
+    pub fn precision(&self) -> &Precision {
+        &self.precision
+    }
+
+    /// Trial balance of the journals of all the accounts
+    pub fn trial_balance(&self) -> TrialBalance {
+        let mut tb = TrialBalance::new(&self.precision);
+        for account in self.accounts.values() {
+            account.add_to_trial_balance(&mut tb);
+        }
+        tb
+    }
+
+    /// Sizes of the engine: accounts, stored transactions, locked accounts and held funds
+    pub fn stats(&self) -> DbStats {
+        let mut stats = DbStats {
+            accounts: self.accounts.len(),
+            ..DbStats::default()
+        };
+
+        for account in self.accounts.values() {
+            stats.transactions += account.stored_transactions();
+            if account.is_locked() {
+                stats.locked += 1;
+            }
+            for asset in account.assets() {
+                *stats.held.entry(asset.clone()).or_default() += account.balance(&asset).held;
+            }
+        }
+
+        stats
+    }
+
+    /// History of the balance changes of the client
+    pub fn history(&self, client: u16) -> Option<Vec<LedgerEntry>> {
+        self.get_account(client).map(|a| a.history())
+    }
+
+    /// Balances of the client per asset at the point of the history
+    pub fn balances_as_of(&self, client: u16, point: AsOf) -> Option<BTreeMap<String, Balance>> {
+        self.get_account(client)?.balances_as_of(point)
+    }
+
+    /// Main entrypoint for a new transaction. Checks the limits, applies the transaction and its fees, atomically.
+    /// The fraud rules look at it, then the balance changes are recorded into the history of every touched account
     pub fn process_new_transaction(&mut self, t: Transaction) -> Result<(), DBError> {
+        let mut change = self.pending_change(&t);
+        change.time = self.time_of(&t);
+
+        let result = self.check_house_account(&t)
+            .and_then(|_| self.round_amount(t))
+            .and_then(|t| self.process_monitored(t, &mut change));
+        self.record_change(change, &result);
+
+        result
+    }
+
+    /// Records the balance changes into the history of every touched account, and pushes the events if there is a sink
+    fn record_change(&self, change: PendingChange, result: &Result<(), DBError>) {
+        for (id, before, was_locked) in change.before {
+            let account = match self.get_account(id) {
+                Some(account) => account,
+                None => continue,
+            };
+
+            let after = account.balance(&change.asset);
+            if result.is_ok() {
+                // The fees are recorded as separate entries, after the transaction
+                let executed = change.before_fees.iter()
+                    .find(|(fee_id, _)| *fee_id == id)
+                    .map_or(after, |(_, balance)| *balance);
+                if executed != before {
+                    account.record_change(LedgerEntry::new(change.client, change.tx, change.r#type.clone(), &change.asset, change.timestamp, before, executed));
+                }
+                if after != executed {
+                    account.record_change(LedgerEntry::new(change.client, change.tx, change.r#type.clone(), &change.asset, change.timestamp, executed, after).into_fee());
+                }
+            }
+            account.compact();
+
+            if let Some(events) = &self.events {
+                let rejected = result.is_err() && id == change.client;
+                if after != before || account.is_locked() != was_locked || rejected {
+                    let precision = self.precision.for_asset(&change.asset);
+                    events(AccountEvent {
+                        client: id,
+                        origin: change.client,
+                        tx: change.tx,
+                        r#type: change.r#type.clone(),
+                        asset: change.asset.clone(),
+                        available: precision.format(after.available),
+                        held: precision.format(after.held),
+                        total: precision.format(after.total()),
+                        locked: account.is_locked(),
+                        outcome: match result {
+                            Ok(_) => "ok".to_string(),
+                            Err(e) => e.to_string(),
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    /// Event time of the transaction: its timestamp, or the time of the clock
+    fn time_of(&mut self, t: &Transaction) -> u64 {
+        if let Some(timestamp) = t.timestamp() {
+            self.latest = self.latest.max(timestamp);
+            return timestamp
+        }
+
+        match self.clock {
+            Clock::Wall => now_ms(),
+            Clock::Input => self.latest,
+        }
+    }
+
+    /// Rejects the transactions of the house account and the transfers to it, if the fees are charged
+    fn check_house_account(&self, t: &Transaction) -> Result<(), DBError> {
+        let house = self.fees.house_account;
+        if !self.fees.is_empty() && (t.client() == house || t.counterparty() == Some(house)) {
+            return Err(DBError::HouseAccount)
+        }
+
+        Ok(())
+    }
+
+    /// Rounds the amount of a deposit, withdrawal or transfer to the precision of its asset, or rejects it.
+    /// Other transactions don't use their amounts
+    fn round_amount(&self, mut t: Transaction) -> Result<Transaction, DBError> {
+        if !matches!(t.get_type(), TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer) {
+            return Ok(t)
+        }
+
+        if let Some(amount) = t.amount() {
+            let precision = self.precision.for_asset(t.asset());
+            let rounded = precision.round_input(amount).ok_or(DBError::TooPrecise(precision.decimals))?;
+            t.set_amount(rounded);
+        }
+
+        Ok(t)
+    }
+
+    /// Remembers the balances the transaction could change, in the asset it works with
+    fn pending_change(&self, t: &Transaction) -> PendingChange {
+        let asset = match t.get_type() {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.get_account(t.client())
+                    .and_then(|a| a.get_transaction(t.tx()))
+                    .map_or_else(|| t.asset().to_string(), |stored| stored.asset().to_string())
+            },
+            _ => t.asset().to_string(),
+        };
+
+        let mut ids = self.touched(t);
+        ids.sort_unstable();
+        ids.dedup();
+
+        let before = ids.into_iter()
+            .map(|id| match self.get_account(id) {
+                Some(account) => (id, account.balance(&asset), account.is_locked()),
+                None => (id, Balance::default(), false),
+            })
+            .collect();
+
+        PendingChange {
+            client: t.client(),
+            tx: t.tx(),
+            r#type: t.get_type().clone(),
+            asset,
+            timestamp: t.timestamp(),
+            time: 0,
+            before,
+            before_fees: Vec::new(),
+        }
+    }
+
+    /// Checks the fraud rules around the transaction, flagging (and optionally locking) the account
+    fn process_monitored(&mut self, t: Transaction, change: &mut PendingChange) -> Result<(), DBError> {
+
+        if self.monitor.is_empty() {
+            return self.process_with_limits(t, change)
+        }
+
+        let activity = Activity {
+            r#type: t.get_type().clone(),
+            client: t.client(),
+            tx: t.tx(),
+            amount: t.amount(),
+            available: self.get_account(t.client()).map(|a| a.balance(t.asset()).available).unwrap_or_default(),
+            time: change.time,
+        };
+
+        let result = self.process_with_limits(t, change);
+
+        let raised = self.monitor.observe(&activity, result.is_ok());
+        if !raised.is_empty() && self.monitor.auto_lock() {
+            if let Some(account) = self.get_account(activity.client) {
+                account.lock();
+            }
+        }
+
+        result
+    }
+
+    /// Checks the limits, applies the transaction and its fees, atomically
+    fn process_with_limits(&mut self, t: Transaction, change: &mut PendingChange) -> Result<(), DBError> {
+
+        if self.limits.is_empty() {
+            return self.process_with_fees(t, change)
+        }
+
+        let limit = self.limits.for_client(t.client());
+        let time = change.time;
+        if let Some(account) = self.get_account(t.client()) {
+            account.test_limits(&t, time, &limit)?;
+        }
+
+        let r#type = t.get_type().clone();
+        let client = t.client();
+        let asset = t.asset().to_string();
+        let amount = t.amount();
+
+        self.process_with_fees(t, change)?;
+
+        if let Some(account) = self.get_account(client) {
+            account.record_limits(&r#type, &asset, amount, time, &limit);
+        }
+
+        Ok(())
+    }
+
+    /// Applies the transaction and its fees, atomically. Remembers the balances between them, to record the fees separately
+    fn process_with_fees(&mut self, t: Transaction, change: &mut PendingChange) -> Result<(), DBError> {
+
+        if self.fees.is_empty() {
+            return self.execute_transaction(t)
+        }
+
+        let r#type = t.get_type().clone();
+        let client = t.client();
+        let tx = t.tx();
+
+        let checkpoints = self.checkpoints(&t);
+
+        self.execute_transaction(t)?;
+        change.before_fees = change.before.iter()
+            .map(|(id, _, _)| (*id, self.get_account(*id).map(|a| a.balance(&change.asset)).unwrap_or_default()))
+            .collect();
+        if let Err(e) = self.apply_fees(&r#type, client, tx) {
+            self.restore(checkpoints);
+            return Err(e)
+        }
+
+        Ok(())
+    }
+
+    /// Remembers the state of every account the transaction could touch. `None` for the accounts which don't exist yet.
+    /// The tx is the client's own, it's not looked up at the other accounts
+    fn checkpoints(&self, t: &Transaction) -> Vec<(u16, Option<Checkpoint>)> {
+        self.touched(t).into_iter()
+            .map(|id| (id, self.get_account(id).map(|a| a.checkpoint(Some(t.tx()).filter(|_| id == t.client())))))
+            .collect()
+    }
+
+    /// Accounts the transaction could touch: the client, the house, the counterparty of a transfer
+    fn touched(&self, t: &Transaction) -> Vec<u16> {
+        let mut ids = vec![t.client(), self.fees.house_account];
+        ids.extend(t.counterparty());
+        ids.extend(self.transfer_counterparty(t));
+
+        ids
+    }
+
+    /// Rolls the accounts back to the checkpoints, removing the ones created since
+    fn restore(&mut self, checkpoints: Vec<(u16, Option<Checkpoint>)>) {
+        for (id, checkpoint) in checkpoints.into_iter().rev() {
+            match checkpoint {
+                Some(checkpoint) => {
+                    if let Some(account) = self.get_account(id) {
+                        account.restore(checkpoint);
+                    }
+                },
+                None => {
+                    self.accounts.remove(&id);
+                },
+            }
+        }
+    }
+
+    /// Applies the fees after the transaction was executed.
+    /// Dispute-related transactions hold, release or refund the fee of the referenced transaction at the house account.
+    /// Then the fee for the transaction type itself is charged from the client's available funds.
+    fn apply_fees(&mut self, r#type: &TransactionType, client: u16, tx: u32) -> Result<(), DBError> {
+        let house = self.fees.house_account;
+        let stored = match self.get_account(client).and_then(|a| a.get_transaction(tx)) {
+            Some(stored) => stored,
+            None => return Ok(()),
+        };
+        let asset = stored.asset().to_string();
+
+        if let Some(fee) = stored.fee() {
+            let house_account = self.get_account(house).ok_or(DBError::AccountNotFound)?;
+            match r#type {
+                TransactionType::Dispute => house_account.dispute(&asset, fee)?,
+                TransactionType::Resolve => house_account.resolve(&asset, fee)?,
+                TransactionType::Chargeback => {
+                    house_account.take_held(&asset, fee)?;
+                    self.get_account(client).ok_or(DBError::AccountNotFound)?.receive(&asset, fee)?;
+                },
+                _ => {},
+            }
+        }
+
+        let mut fee = match self.fees.fee(r#type, stored.amount().unwrap_or_default(), &self.precision.for_asset(&asset)) {
+            Some(fee) => fee,
+            None => return Ok(()),
+        };
+
+        // Chargeback is a fact, its fee can't roll it back: only what's available is charged, the rest is waived
+        if *r#type == TransactionType::Chargeback {
+            let available = self.get_account(client).ok_or(DBError::AccountNotFound)?.balance(&asset).available;
+            fee = fee.min(available.max(Monetary::ZERO));
+            if fee.is_zero() {
+                return Ok(())
+            }
+        }
+
+        if !self.accounts.contains_key(&house) {
+            self.add_account(self.new_account(house));
+        }
+
+        let account = self.get_account(client).ok_or(DBError::AccountNotFound)?;
+        account.pay(&asset, fee)?;
+        if matches!(r#type, TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer) {
+            account.set_transaction_fee(tx, fee);
+        }
+
+        self.get_account(house).ok_or(DBError::AccountNotFound)?.receive(&asset, fee)?;
+
+        Ok(())
+    }
+
+    /// Executes the transaction itself, without the fees
+    fn execute_transaction(&mut self, t: Transaction) -> Result<(), DBError> {
+
+        match t.get_type() {
+            TransactionType::Transfer => {
+                return self.process_transfer(t)
+            },
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if let Some(counterparty) = self.transfer_counterparty(&t) {
+                    return self.process_transfer_dispute(t, counterparty)
+                }
+            },
+            _ => {},
+        }
 
         if let Some(account) = self.get_account_mut(&t.client()) {
             account.execute_transaction(t).map_err::<DBError, _>(|x| x.into())?;
             Ok(())
         } else if t.get_type() == &TransactionType::Deposit {
-            let account = Account::empty(t.client());
+            let account = self.new_account(t.client());
             account.execute_transaction(t).map_err::<DBError, _>(|x| x.into())?;
             self.add_account(account);
             Ok(())
@@ -66,15 +558,613 @@ impl Db {
         }
         
     }
-}
 
+    /// If the dispute-related transaction refers to a stored transfer, returns the counterparty of the transfer
+    fn transfer_counterparty(&self, t: &Transaction) -> Option<u16> {
+        let stored = self.get_account(t.client())?.get_transaction(t.tx())?;
+
+        if stored.get_type() == &TransactionType::Transfer {
+            stored.counterparty()
+        } else {
+            None
+        }
+    }
+
+    /// Moves funds between two accounts atomically: if the counterparty can't receive them, the sender gets them back.
+    /// The counterparty account is created, if it doesn't exist yet.
+    fn process_transfer(&mut self, t: Transaction) -> Result<(), DBError> {
+        let to = t.counterparty().ok_or(DBError::CounterpartyNotSet)?;
+        if to == t.client() {
+            return Err(DBError::TransferToItself)
+        }
+
+        let from = t.client();
+        let tx = t.tx();
+        let asset = t.asset().to_string();
+        let amount = t.amount().ok_or(AccountError::TransactionIsEmpty)?;
+
+        let new_recipient = if self.accounts.contains_key(&to) {
+            None
+        } else {
+            Some(self.new_account(to))
+        };
+
+        {
+            let sender = self.get_account(from).ok_or(DBError::AccountNotFound)?;
+            let recipient = match &new_recipient {
+                Some(account) => account,
+                None => self.get_account(to).ok_or(DBError::AccountNotFound)?,
+            };
+
+            sender.try_transfer_out(t)?;
+            if let Err(e) = recipient.receive_transfer(&asset, amount) {
+                sender.revert_transfer_out(tx);
+                return Err(e.into())
+            }
+        }
+
+        if let Some(account) = new_recipient {
+            self.add_account(account);
+        }
+
+        Ok(())
+    }
+
+    /// Dispute, resolve or chargeback of a transfer, which involves both accounts
+    fn process_transfer_dispute(&mut self, t: Transaction, counterparty: u16) -> Result<(), DBError> {
+        let sender = self.get_account(t.client()).ok_or(DBError::AccountNotFound)?;
+        let recipient = self.get_account(counterparty).ok_or(DBError::AccountNotFound)?;
+
+        sender.try_transfer_dispute(t, recipient)?;
+        Ok(())
+    }
+}
 
 impl fmt::Display for Db {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "client, available, held, total, locked")?;
+        // Legacy single-asset input keeps the output without the asset column
+        let with_asset = self.accounts.values().any(|account| account.has_assets());
+        let header = if with_asset {
+            "client, asset, available, held, total, locked"
+        } else {
+            "client, available, held, total, locked"
+        };
+
+        if self.monitor.is_empty() {
+            writeln!(f, "{}", header)?;
+            for account in self.accounts.values() {
+                write!(f, "{}", account.rows(with_asset))?;
+            }
+            return Ok(())
+        }
+
+        writeln!(f, "{}, flags", header)?;
         for account in self.accounts.values() {
-            write!(f, "{}", account)?;
+            let mut flags: Vec<_> = self.monitor.flags(account.get_id()).iter().map(|r| r.flag).collect();
+            flags.sort();
+            flags.dedup();
+            let flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+            for row in account.rows(with_asset).to_string().lines() {
+                writeln!(f, "{}, {}", row, flags.join(" "))?;
+            }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use account::balance::Balance;
+    use transaction::DEFAULT_ASSET;
+
+    use rust_decimal_macros::dec;
+
+    fn deposit(db: &mut Db, client: u16, tx: u32, amount: rust_decimal::Decimal) {
+        let t = Transaction::new(TransactionType::Deposit, client, tx, Some(amount), false);
+        db.process_new_transaction(t).unwrap();
+    }
+
+    fn transfer(client: u16, tx: u32, amount: rust_decimal::Decimal, to: u16) -> Transaction {
+        Transaction::new(TransactionType::Transfer, client, tx, Some(amount), false).with_counterparty(to)
+    }
+
+    fn balance(db: &Db, client: u16) -> Balance {
+        db.get_account(client).unwrap().balance(DEFAULT_ASSET)
+    }
+
+    #[test]
+    fn asset_column_only_with_assets() {
+        let mut db = Db::default();
+        deposit(&mut db, 1, 1, dec!(10));
+        assert_eq!(format!("{}", db), "client, available, held, total, locked\n1, 10.0000, 0.0000, 10.0000, false\n");
+
+        db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 2, Some(dec!(2)), false).with_asset("EUR")).unwrap();
+        assert_eq!(format!("{}", db), concat!(
+            "client, asset, available, held, total, locked\n",
+            "1, , 10.0000, 0.0000, 10.0000, false\n",
+            "1, EUR, 2.0000, 0.0000, 2.0000, false\n",
+        ));
+    }
+
+    #[test]
+    fn events_of_the_changed_accounts() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let mut db = Db::default().with_events(move |e| sink.lock().unwrap().push(e));
+
+        deposit(&mut db, 1, 1, dec!(10));
+        assert!(db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(11)), false)).is_err());
+        db.process_new_transaction(transfer(1, 3, dec!(4), 2)).unwrap();
+        deposit(&mut db, 1, 4, dec!(2));
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 4, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 1, 4, None, false)).unwrap();
+
+        let events = events.lock().unwrap();
+        let summary: Vec<_> = events.iter()
+            .map(|e| (e.client, e.tx, e.available.as_str(), e.held.as_str(), e.locked, e.outcome == "ok"))
+            .collect();
+        assert_eq!(summary, vec![
+            (1, 1, "10.0000", "0.0000", false, true),
+            // Rejected, balances didn't change
+            (1, 2, "10.0000", "0.0000", false, false),
+            (1, 3, "6.0000", "0.0000", false, true),
+            (2, 3, "4.0000", "0.0000", false, true),
+            (1, 4, "8.0000", "0.0000", false, true),
+            (1, 4, "6.0000", "2.0000", false, true),
+            (1, 4, "6.0000", "0.0000", true, true),
+        ]);
+    }
+
+    #[test]
+    fn transfer_moves_funds() {
+        let mut db = Db::default();
+        deposit(&mut db, 1, 1, dec!(10));
+
+        assert!(db.process_new_transaction(transfer(1, 2, dec!(4), 2)).is_ok());
+
+        assert_eq!(balance(&db, 1), Balance::new(dec!(6), dec!(0)));
+        assert_eq!(balance(&db, 2), Balance::new(dec!(4), dec!(0)));
+
+        assert!(matches!(
+            db.process_new_transaction(transfer(1, 3, dec!(7), 2)),
+            Err(DBError::AccountError(AccountError::TooMuch(_)))
+        ));
+        assert!(matches!(db.process_new_transaction(transfer(1, 3, dec!(1), 1)), Err(DBError::TransferToItself)));
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Transfer, 1, 3, Some(dec!(1)), false)),
+            Err(DBError::CounterpartyNotSet)
+        ));
+        assert!(matches!(db.process_new_transaction(transfer(3, 3, dec!(1), 1)), Err(DBError::AccountNotFound)));
+    }
+
+    #[test]
+    fn transfer_rolls_back() {
+        let mut db = Db::default();
+        deposit(&mut db, 1, 1, dec!(10));
+        deposit(&mut db, 2, 1, dec!(10));
+
+        // Lock the counterparty
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 2, 1, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 2, 1, None, false)).unwrap();
+
+        assert!(matches!(
+            db.process_new_transaction(transfer(1, 2, dec!(4), 2)),
+            Err(DBError::AccountError(AccountError::AccountLocked))
+        ));
+
+        assert_eq!(balance(&db, 1), Balance::new(dec!(10), dec!(0)));
+        assert!(db.get_account(1).unwrap().get_transaction(2).is_none());
+
+        // The same tx could be used again after the rollback
+        deposit(&mut db, 3, 1, dec!(0));
+        assert!(db.process_new_transaction(transfer(1, 2, dec!(4), 3)).is_ok());
+    }
+
+    #[test]
+    fn transfer_is_disputed_as_a_unit() {
+        let mut db = Db::default();
+        deposit(&mut db, 1, 1, dec!(10));
+        db.process_new_transaction(transfer(1, 2, dec!(4), 2)).unwrap();
+
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 2, None, false)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(6), dec!(0)));
+        assert_eq!(balance(&db, 2), Balance::new(dec!(0), dec!(4)));
+
+        db.process_new_transaction(Transaction::new(TransactionType::Resolve, 1, 2, None, false)).unwrap();
+        assert_eq!(balance(&db, 2), Balance::new(dec!(4), dec!(0)));
+
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 2, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 1, 2, None, false)).unwrap();
+
+        assert_eq!(balance(&db, 1), Balance::new(dec!(10), dec!(0)));
+        assert_eq!(balance(&db, 2), Balance::new(dec!(0), dec!(0)));
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 3, Some(dec!(1)), false)),
+            Err(DBError::AccountError(AccountError::AccountLocked))
+        ));
+    }
+
+    fn with_fees() -> Db {
+        let config: Config = serde_json::from_str(r#"{
+            "fees": {
+                "house_account": 0,
+                "fees": {
+                    "withdrawal": {"flat": 1},
+                    "chargeback": {"flat": 2}
+                }
+            }
+        }"#).unwrap();
+
+        Db::new(&config)
+    }
+
+    #[test]
+    fn fees_go_to_the_house() {
+        let mut db = with_fees();
+        deposit(&mut db, 1, 1, dec!(10));
+
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)), false)).unwrap();
+
+        assert_eq!(balance(&db, 1), Balance::new(dec!(5), dec!(0)));
+        assert_eq!(balance(&db, 0), Balance::new(dec!(1), dec!(0)));
+        assert_eq!(db.get_account(1).unwrap().get_transaction(2).unwrap().fee(), Some(dec!(1)));
+
+        // Withdrawal itself is possible, but not together with the fee: nothing changes
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 3, Some(dec!(4.5)), false)),
+            Err(DBError::AccountError(AccountError::TooMuch(_)))
+        ));
+        assert_eq!(balance(&db, 1), Balance::new(dec!(5), dec!(0)));
+        assert_eq!(balance(&db, 0), Balance::new(dec!(1), dec!(0)));
+        assert!(db.get_account(1).unwrap().get_transaction(3).is_none());
+    }
+
+    #[test]
+    fn fees_are_reversed_by_disputes() {
+        let mut db = with_fees();
+        deposit(&mut db, 1, 1, dec!(10));
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)), false)).unwrap();
+
+        // Dispute of the withdrawal holds the fee at the house
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 2, None, false)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(1), dec!(4)));
+        assert_eq!(balance(&db, 0), Balance::new(dec!(0), dec!(1)));
+
+        db.process_new_transaction(Transaction::new(TransactionType::Resolve, 1, 2, None, false)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(5), dec!(0)));
+        assert_eq!(balance(&db, 0), Balance::new(dec!(1), dec!(0)));
+
+        // Chargeback refunds the fee of the withdrawal and charges its own fee
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 2, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 1, 2, None, false)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(0), dec!(0)));
+        assert_eq!(balance(&db, 0), Balance::new(dec!(2), dec!(0)));
+    }
+
+    #[test]
+    fn unpaid_fee_doesnt_veto_the_chargeback() {
+        let mut db = with_fees();
+        deposit(&mut db, 1, 1, dec!(10));
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None, false)).unwrap();
+
+        // Nothing available to pay the chargeback fee, it's waived
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 1, 1, None, false)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(0), dec!(0)));
+        assert!(db.get_account(1).unwrap().is_locked());
+        assert!(db.get_account(0).is_none());
+
+        // Only a part of it is available
+        deposit(&mut db, 2, 1, dec!(10));
+        deposit(&mut db, 2, 2, dec!(1));
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 2, 1, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 2, 1, None, false)).unwrap();
+        assert_eq!(balance(&db, 2), Balance::new(dec!(0), dec!(0)));
+        assert!(db.get_account(2).unwrap().is_locked());
+        assert_eq!(balance(&db, 0), Balance::new(dec!(1), dec!(0)));
+        assert!(db.trial_balance().problems().is_empty());
+    }
+
+    fn with_limits() -> Db {
+        let config: Config = serde_json::from_str(r#"{
+            "limits": {
+                "global": {
+                    "max_withdrawal": 5,
+                    "rolling_withdrawals": {"window_ms": 1000, "max_volume": 10},
+                    "max_disputes": 1
+                },
+                "clients": {"2": {"min_balance": 1}}
+            }
+        }"#).unwrap();
+
+        Db::new(&config)
+    }
+
+    #[test]
+    fn withdrawal_limits() {
+        let mut db = with_limits();
+        deposit(&mut db, 1, 1, dec!(100));
+
+        let withdraw = |tx, amount, at| Transaction::new(TransactionType::Withdrawal, 1, tx, Some(amount), false).with_timestamp(at);
+
+        assert!(matches!(
+            db.process_new_transaction(withdraw(2, dec!(6), 0)),
+            Err(DBError::AccountError(AccountError::WithdrawalLimit(_)))
+        ));
+
+        db.process_new_transaction(withdraw(3, dec!(4), 1000)).unwrap();
+        db.process_new_transaction(withdraw(4, dec!(4), 1500)).unwrap();
+        match db.process_new_transaction(withdraw(5, dec!(4), 1999)) {
+            Err(DBError::AccountError(AccountError::WithdrawalVolumeLimit(allowed))) => assert_eq!(allowed, dec!(2)),
+            other => panic!("unexpected {:?}", other),
+        }
+        db.process_new_transaction(withdraw(6, dec!(2), 1999)).unwrap();
+
+        // The first withdrawal of 4 left the window, however many withdrawals were there
+        db.process_new_transaction(withdraw(7, dec!(4), 2000)).unwrap();
+        assert!(db.process_new_transaction(withdraw(8, dec!(1), 2000)).is_err());
+        // Nothing is left in the window after a quiet second
+        db.process_new_transaction(withdraw(9, dec!(5), 4000)).unwrap();
+        db.process_new_transaction(withdraw(10, dec!(5), 4001)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(76), dec!(0)));
+    }
+
+    #[test]
+    fn input_clock() {
+        let mut db = with_limits().with_clock(Clock::Input);
+        deposit(&mut db, 1, 1, dec!(100));
+
+        let withdraw = |tx, amount| Transaction::new(TransactionType::Withdrawal, 1, tx, Some(amount), false);
+
+        // At the time of the latest timestamp, however long it takes to get here
+        db.process_new_transaction(withdraw(2, dec!(5)).with_timestamp(5000)).unwrap();
+        db.process_new_transaction(withdraw(3, dec!(5))).unwrap();
+        assert!(db.process_new_transaction(withdraw(4, dec!(1))).is_err());
+        db.process_new_transaction(withdraw(5, dec!(5)).with_timestamp(6000)).unwrap();
+        db.process_new_transaction(withdraw(6, dec!(5))).unwrap();
+        assert!(db.process_new_transaction(withdraw(7, dec!(1))).is_err());
+    }
+
+    #[test]
+    fn disputes_and_balance_limits() {
+        let mut db = with_limits();
+        deposit(&mut db, 2, 1, dec!(2));
+        deposit(&mut db, 2, 2, dec!(2));
+
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 2, 3, Some(dec!(3.5)), false)),
+            Err(DBError::AccountError(AccountError::MinimumBalance(_)))
+        ));
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 2, 3, Some(dec!(3)), false)).unwrap();
+
+        deposit(&mut db, 2, 4, dec!(3));
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 2, 4, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Resolve, 2, 4, None, false)).unwrap();
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Dispute, 2, 4, None, false)),
+            Err(DBError::AccountError(AccountError::DisputesLimit(1)))
+        ));
+    }
+
+    #[test]
+    fn flagged_accounts_are_locked() {
+        let config: Config = serde_json::from_str(r#"{
+            "rules": {"drain_after_deposit": 90, "auto_lock": true}
+        }"#).unwrap();
+        let mut db = Db::new(&config);
+
+        deposit(&mut db, 1, 1, dec!(10));
+        deposit(&mut db, 2, 1, dec!(10));
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(9.5)), false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 2, 2, Some(dec!(1)), false)).unwrap();
+
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 3, Some(dec!(1)), false)),
+            Err(DBError::AccountError(AccountError::AccountLocked))
+        ));
+        assert!(db.monitor().flags(2).is_empty());
+
+        let state = format!("{}", db);
+        assert!(state.starts_with("client, available, held, total, locked, flags"));
+        assert!(state.contains("1, 0.5000, 0.0000, 0.5000, true, drain_after_deposit"));
+        assert!(state.contains("2, 9.0000, 0.0000, 9.0000, false, \n"));
+    }
+
+    #[test]
+    fn rules_follow_the_input_clock() {
+        let config: Config = serde_json::from_str(r#"{
+            "rules": {"dispute_burst": {"window_ms": 1000, "disputes": 2}}
+        }"#).unwrap();
+        let mut db = Db::new(&config).with_clock(Clock::Input);
+
+        deposit(&mut db, 1, 1, dec!(10));
+        deposit(&mut db, 1, 2, dec!(10));
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None, false).with_timestamp(5000)).unwrap();
+        // At 5000 too, not whenever it's processed
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 2, None, false)).unwrap();
+
+        assert_eq!(db.monitor().flags(1).len(), 1);
+    }
+
+    #[test]
+    fn history_of_every_touched_account() {
+        let mut db = with_fees();
+        deposit(&mut db, 1, 1, dec!(10));
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)), false).with_timestamp(200)).unwrap();
+        deposit(&mut db, 1, 3, dec!(2));
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 3, None, false).with_timestamp(300)).unwrap();
+        // Rejected, nothing recorded
+        assert!(db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 4, Some(dec!(100)), false)).is_err());
+
+        let history = db.history(1).unwrap();
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[1].available_delta, dec!(-4));
+        assert!(!history[1].fee);
+        // The fee of the withdrawal is an entry of its own
+        assert_eq!((history[2].tx, history[2].fee), (2, true));
+        assert_eq!(history[2].available_delta, dec!(-1));
+        assert_eq!(history[2].balance, Balance::new(dec!(5), dec!(0)));
+        assert_eq!(history[2].format(&Default::default()), "1, 2, withdrawal-fee, , 200, -1.0000, 0.0000, 5.0000, 0.0000\n");
+        assert_eq!(history[4].r#type, TransactionType::Dispute);
+        assert_eq!(history[4].held_delta, dec!(2));
+        assert_eq!(history[4].balance, Balance::new(dec!(5), dec!(2)));
+
+        let house = db.history(0).unwrap();
+        assert_eq!(house.len(), 1);
+        assert_eq!((house[0].client, house[0].tx, house[0].fee), (1, 2, true));
+        assert_eq!(house[0].available_delta, dec!(1));
+
+        let as_of = |client, tx| AsOf::Tx { client, tx };
+        assert_eq!(db.balances_as_of(1, as_of(1, 2)).unwrap().get(DEFAULT_ASSET), Some(&Balance::new(dec!(5), dec!(0))));
+        // The deposit without a timestamp happened at the time of the withdrawal
+        assert_eq!(db.balances_as_of(1, AsOf::Time(250)).unwrap().get(DEFAULT_ASSET), Some(&Balance::new(dec!(7), dec!(0))));
+        assert!(db.balances_as_of(1, as_of(1, 4)).is_none());
+        assert!(db.balances_as_of(2, as_of(2, 1)).is_none());
+
+        // The house account keys the fees by the client, whose tx 2 isn't the one of another client
+        deposit(&mut db, 2, 1, dec!(10));
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 2, 2, Some(dec!(1)), false)).unwrap();
+        assert_eq!(db.balances_as_of(0, as_of(1, 2)).unwrap().get(DEFAULT_ASSET), Some(&Balance::new(dec!(1), dec!(0))));
+        assert_eq!(db.balances_as_of(0, as_of(2, 2)).unwrap().get(DEFAULT_ASSET), Some(&Balance::new(dec!(2), dec!(0))));
+        assert!(db.balances_as_of(0, as_of(3, 2)).is_none());
+    }
+
+    #[test]
+    fn house_account_isnt_a_client() {
+        let mut db = with_fees();
+        deposit(&mut db, 1, 1, dec!(10));
+
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Deposit, 0, 1, Some(dec!(1)), false)),
+            Err(DBError::HouseAccount)
+        ));
+        assert!(matches!(db.process_new_transaction(transfer(1, 2, dec!(1), 0)), Err(DBError::HouseAccount)));
+        assert!(db.get_account(0).is_none());
+
+        // Without fees there is no house account
+        let mut db = Db::default();
+        deposit(&mut db, u16::MAX, 1, dec!(1));
+    }
+
+    #[test]
+    fn books_balance() {
+        use account::journal::Book;
+        use rand::prelude::*;
+        use rand::rngs::StdRng;
+
+        let config: Config = serde_json::from_str(r#"{
+            "fees": {
+                "house_account": 0,
+                "fees": {
+                    "deposit": {"percentage": 1},
+                    "transfer": {"flat": 0.1},
+                    "dispute": {"flat": 0.5},
+                    "chargeback": {"flat": 2}
+                }
+            }
+        }"#).unwrap();
+        let mut db = Db::new(&config);
+
+        let types = [
+            TransactionType::Deposit, TransactionType::Withdrawal, TransactionType::Dispute,
+            TransactionType::Resolve, TransactionType::Chargeback, TransactionType::Transfer,
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20_000 {
+            let r#type = types[rng.gen_range(0..types.len())].clone();
+            let amount = rust_decimal::Decimal::new(rng.gen_range(0..100_000), 2);
+            let mut t = Transaction::new(r#type, rng.gen_range(1..7), rng.gen_range(0..200), Some(amount), false)
+                .with_counterparty(rng.gen_range(1..7));
+            if rng.gen_bool(0.3) {
+                t = t.with_asset("EUR");
+            }
+            let _ = db.process_new_transaction(t);
+        }
+
+        let tb = db.trial_balance();
+        assert!(tb.is_balanced(), "{}", tb);
+        assert!(tb.turnover(DEFAULT_ASSET, Book::ChargebackLoss).net() < Monetary::ZERO);
+        assert!(!tb.turnover("EUR", Book::Clearing).debit.is_zero());
+    }
+
+    #[test]
+    fn amounts_follow_the_precision() {
+        let config: Config = serde_json::from_str(r#"{
+            "precision": {
+                "default": {"decimals": 2, "rounding": "reject"},
+                "assets": {"BTC": {"decimals": 8, "rounding": "truncate"}}
+            },
+            "fees": {"house_account": 0, "fees": {"withdrawal": {"percentage": 0.5}}}
+        }"#).unwrap();
+        let mut db = Db::new(&config);
+
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(1.005)), false)),
+            Err(DBError::TooPrecise(2))
+        ));
+        deposit(&mut db, 1, 1, dec!(10.5));
+        db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 2, Some(dec!(0.123456789)), false).with_asset("BTC")).unwrap();
+        // The fee of 0.5% is 0.0055, rounded to 0.01
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 3, Some(dec!(1.1)), false)).unwrap();
+
+        assert_eq!(balance(&db, 1), Balance::new(dec!(9.39), dec!(0)));
+        assert_eq!(db.get_account(1).unwrap().balance("BTC").available, dec!(0.12345678));
+
+        let state = format!("{}", db);
+        assert!(state.contains("1, , 9.39, 0.00, 9.39, false"));
+        assert!(state.contains("1, BTC, 0.12345678, 0.00000000, 0.12345678, false"));
+    }
+
+    #[test]
+    fn evicted_transactions_cant_be_disputed() {
+        let config: Config = serde_json::from_str(r#"{"retention": {"max_transactions": 2}}"#).unwrap();
+        let mut db = Db::new(&config);
+        let dispute = |tx| Transaction::new(TransactionType::Dispute, 1, tx, None, false);
+
+        deposit(&mut db, 1, 1, dec!(1));
+        deposit(&mut db, 1, 2, dec!(1));
+        deposit(&mut db, 1, 3, dec!(1));
+
+        assert!(matches!(
+            db.process_new_transaction(dispute(1)),
+            Err(DBError::AccountError(AccountError::TransactionEvicted))
+        ));
+        assert!(matches!(
+            db.process_new_transaction(dispute(4)),
+            Err(DBError::AccountError(AccountError::TransactionNotFound))
+        ));
+        // Still known, so its id can't be reused
+        assert!(db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(1)), false)).is_err());
+
+        db.process_new_transaction(dispute(2)).unwrap();
+        assert_eq!(balance(&db, 1), Balance::new(dec!(2), dec!(1)));
+    }
+
+    #[test]
+    fn rollback_keeps_the_evicted_ids() {
+        let config: Config = serde_json::from_str(r#"{
+            "retention": {"max_transactions": 1},
+            "fees": {"fees": {"transfer": {"flat": 100}}}
+        }"#).unwrap();
+        let mut db = Db::new(&config);
+
+        deposit(&mut db, 1, 1, dec!(10));
+        deposit(&mut db, 1, 2, dec!(10));
+        deposit(&mut db, 2, 5, dec!(5));
+        // Can't pay the fee, rolled back. The tx is the one evicted at the counterparty
+        assert!(db.process_new_transaction(transfer(2, 1, dec!(1), 1)).is_err());
+
+        assert!(matches!(
+            db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(7)), false)),
+            Err(DBError::AccountError(AccountError::TransactionAlreadyExists))
+        ));
+        assert_eq!(balance(&db, 1), Balance::new(dec!(20), dec!(0)));
+
+        // The history and the journal are bounded as well, the books still balance
+        assert_eq!(db.history(1).unwrap().len(), 1);
+        let tb = db.trial_balance();
+        assert!(tb.is_balanced(), "{}", tb);
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Deserialize;
+
+use rust_decimal::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::Monetary;
+
+
+/// Most decimals a Decimal could keep
+const MAX_DECIMALS: u32 = 28;
+
+/// What to do with an amount having more decimals than the asset allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Reject the transaction
+    Reject,
+    /// Round half to even
+    Bankers,
+    /// Round half away from zero
+    HalfUp,
+    /// Cut the extra decimals
+    Truncate,
+}
+
+/// Precision of a single asset
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AssetPrecision {
+    pub decimals: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for AssetPrecision {
+    fn default() -> Self {
+        Self {
+            decimals: 4,
+            rounding: Rounding::Bankers,
+        }
+    }
+}
+
+impl AssetPrecision {
+
+    /// Decimals, limited by what a Decimal could keep
+    fn dp(&self) -> u32 {
+        self.decimals.min(MAX_DECIMALS)
+    }
+
+    /// Rounds the amount with the strategy. Rejecting isn't a strategy, so it's banker's then
+    fn round_with(&self, amount: Monetary, rounding: Rounding) -> Monetary {
+        let strategy = match rounding {
+            Rounding::Reject | Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        };
+
+        amount.round_dp_with_strategy(self.dp(), strategy)
+    }
+
+    /// Rounds an incoming amount. `None` if it has too many decimals and the asset rejects such
+    pub fn round_input(&self, amount: Monetary) -> Option<Monetary> {
+        let rounded = self.round_with(amount, self.rounding);
+        if self.rounding == Rounding::Reject && rounded != amount {
+            None
+        } else {
+            Some(rounded)
+        }
+    }
+
+    /// Rounds a calculated amount, like a fee. Never rejects
+    pub fn round(&self, amount: Monetary) -> Monetary {
+        self.round_with(amount, self.rounding)
+    }
+
+    /// Biggest total a balance could have, so the decimals wouldn't be eaten: Decimal::MAX / 10^decimals
+    pub fn max_total(&self) -> Monetary {
+        (Decimal::MAX / Decimal::from_i128_with_scale(10i128.pow(self.dp()), 0)).trunc()
+    }
+
+    /// Formats the amount with exactly the asset's decimals
+    pub fn format(&self, amount: Monetary) -> String {
+        format!("{:.*}", self.dp() as usize, amount)
+    }
+}
+
+/// Precision per asset, the default one for the assets not listed
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Precision {
+    pub default: AssetPrecision,
+    pub assets: HashMap<String, AssetPrecision>,
+}
+
+impl Precision {
+
+    /// Precision of the asset
+    pub fn for_asset(&self, asset: &str) -> AssetPrecision {
+        self.assets.get(asset).copied().unwrap_or(self.default)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn rounding_modes() {
+        let precision: Precision = serde_json::from_str(r#"{
+            "default": {"decimals": 2, "rounding": "reject"},
+            "assets": {
+                "A": {"decimals": 2, "rounding": "bankers"},
+                "B": {"decimals": 2, "rounding": "half_up"},
+                "C": {"decimals": 2, "rounding": "truncate"},
+                "BTC": {"decimals": 8}
+            }
+        }"#).unwrap();
+
+        assert_eq!(precision.for_asset("").round_input(dec!(1.005)), None);
+        assert_eq!(precision.for_asset("").round_input(dec!(1.01)), Some(dec!(1.01)));
+        assert_eq!(precision.for_asset("").round(dec!(1.005)), dec!(1.00));
+        assert_eq!(precision.for_asset("A").round_input(dec!(1.005)), Some(dec!(1.00)));
+        assert_eq!(precision.for_asset("B").round_input(dec!(1.005)), Some(dec!(1.01)));
+        assert_eq!(precision.for_asset("C").round_input(dec!(1.009)), Some(dec!(1.00)));
+        assert_eq!(precision.for_asset("BTC").round_input(dec!(0.123456789)), Some(dec!(0.12345679)));
+
+        assert_eq!(precision.for_asset("BTC").format(dec!(1.5)), "1.50000000");
+        assert_eq!(precision.for_asset("A").format(dec!(1.5)), "1.50");
+    }
+
+    #[test]
+    fn max_total() {
+        assert_eq!(AssetPrecision::default().max_total(), dec!(7922816251426433759354395));
+        assert_eq!(AssetPrecision { decimals: 0, rounding: Rounding::Bankers }.max_total(), Decimal::MAX);
+        assert_eq!(AssetPrecision { decimals: 100, rounding: Rounding::Bankers }.max_total(), dec!(7));
+    }
+}
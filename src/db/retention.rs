@@ -0,0 +1,367 @@
+use serde::{Deserialize, Serialize};
+use rustc_hash::FxHashMap;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::db::transaction::{Transaction, TransactionType};
+use crate::Monetary;
+
+
+/// How long the deposits, withdrawals and transfers are kept to be disputed, per account.
+/// Without limits everything is kept in memory forever
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    /// Most transactions kept in memory per account, the oldest ones are evicted first
+    pub max_transactions: Option<usize>,
+    /// Transactions older than this (milliseconds of the event time) behind the latest one of the account are evicted
+    pub max_age_ms: Option<u64>,
+    /// Directory to spill the evicted transactions to, so they could still be disputed. Dropped if missing
+    pub spill_dir: Option<PathBuf>,
+    /// Most transactions kept in the spill per account, the oldest ones are dropped
+    pub max_spilled: Option<usize>,
+    /// Most ids of the dropped transactions remembered per account, to tell them apart from the unknown ones.
+    /// The oldest ones are forgotten, then they are not found
+    pub max_evicted: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_transactions: None,
+            max_age_ms: None,
+            spill_dir: None,
+            max_spilled: None,
+            max_evicted: 100_000,
+        }
+    }
+}
+
+impl Retention {
+
+    /// Returns `true` if the transactions are never evicted
+    pub fn is_empty(&self) -> bool {
+        self.max_transactions.is_none() && self.max_age_ms.is_none()
+    }
+}
+
+/// Transaction as it's written to the spill file
+#[derive(Serialize, Deserialize)]
+struct Spilled {
+    r#type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Monetary>,
+    asset: String,
+    counterparty: Option<u16>,
+    timestamp: Option<u64>,
+    fee: Option<Monetary>,
+}
+
+impl From<&Transaction> for Spilled {
+    fn from(t: &Transaction) -> Self {
+        Self {
+            r#type: t.get_type().clone(),
+            client: t.client(),
+            tx: t.tx(),
+            amount: t.amount(),
+            asset: t.asset().to_string(),
+            counterparty: t.counterparty(),
+            timestamp: t.timestamp(),
+            fee: t.fee(),
+        }
+    }
+}
+
+impl From<Spilled> for Transaction {
+    fn from(s: Spilled) -> Self {
+        let mut t = Transaction::new(s.r#type, s.client, s.tx, s.amount, false).with_asset(&s.asset);
+        if let Some(counterparty) = s.counterparty {
+            t = t.with_counterparty(counterparty);
+        }
+        if let Some(timestamp) = s.timestamp {
+            t = t.with_timestamp(timestamp);
+        }
+        if let Some(fee) = s.fee {
+            t.set_fee(fee);
+        }
+        t
+    }
+}
+
+/// Lines in the spill file, under which it's never compacted
+const COMPACT_LINES: usize = 1024;
+
+/// On-disk store of the evicted transactions of an account: a file of json lines, with the offsets kept in memory.
+/// The file is created at the first eviction, overwriting the one left by a previous run. The lines of the transactions
+/// taken back or dropped stay in the file till most of it are such, then it's rewritten without them
+#[derive(Debug)]
+pub(crate) struct Spill {
+    path: PathBuf,
+    file: Option<File>,
+    /// Offset and length of the line per tx
+    index: FxHashMap<u32, (u64, usize)>,
+    /// Spilled tx with the offset, in the order they were written. Entries which offset doesn't match the index are stale
+    order: VecDeque<(u32, u64)>,
+    /// Lines in the file
+    lines: usize,
+}
+
+impl Spill {
+
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            index: FxHashMap::default(),
+            order: VecDeque::new(),
+            lines: 0,
+        }
+    }
+
+    pub(crate) fn contains(&self, tx: u32) -> bool {
+        self.index.contains_key(&tx)
+    }
+
+    /// Appends the transaction to the file
+    pub(crate) fn put(&mut self, t: &Transaction) -> io::Result<()> {
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            self.file = Some(OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&self.path)?);
+        }
+
+        let mut line = serde_json::to_vec(&Spilled::from(t))?;
+        line.push(b'\n');
+
+        if let Some(file) = &mut self.file {
+            let offset = file.seek(SeekFrom::End(0))?;
+            file.write_all(&line)?;
+            self.index.insert(t.tx(), (offset, line.len()));
+            self.order.push_back((t.tx(), offset));
+            self.lines += 1;
+        }
+
+        if self.lines > COMPACT_LINES && self.lines > 2 * self.index.len() {
+            // The transaction is spilled anyway, the file is just bigger
+            if let Err(e) = self.compact() {
+                eprintln!("E: spill {} is not compacted: {}", self.path.display(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the file with the lines of the spilled transactions only, in the same order
+    fn compact(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)?;
+
+        let mut index = FxHashMap::default();
+        let mut order = VecDeque::new();
+        let mut offset = 0;
+        let mut out = BufWriter::new(&file);
+        for &(tx, old) in &self.order {
+            let len = match self.index.get(&tx) {
+                Some(&(o, len)) if o == old => len,
+                _ => continue,
+            };
+            out.write_all(&self.read(old, len)?)?;
+            index.insert(tx, (offset, len));
+            order.push_back((tx, offset));
+            offset += len as u64;
+        }
+        out.flush()?;
+        drop(out);
+
+        // The handle stays valid after the rename
+        fs::rename(&tmp, &self.path)?;
+        self.lines = index.len();
+        self.index = index;
+        self.order = order;
+        self.file = Some(file);
+
+        Ok(())
+    }
+
+    /// Line at the offset
+    fn read(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = self.file.as_ref().ok_or(io::ErrorKind::NotFound)?;
+
+        let mut line = vec![0; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut line)?;
+
+        Ok(line)
+    }
+
+    /// Reads the transaction back, `None` if it's not there or can't be read
+    pub(crate) fn get(&self, tx: u32) -> Option<Transaction> {
+        let (offset, len) = *self.index.get(&tx)?;
+        let line = self.read(offset, len).ok()?;
+
+        serde_json::from_slice::<Spilled>(&line).ok().map(Transaction::from)
+    }
+
+    /// Reads the transaction back and forgets it. The line stays in the file till it's compacted
+    pub(crate) fn take(&mut self, tx: u32) -> Option<Transaction> {
+        let t = self.get(tx)?;
+        self.index.remove(&tx);
+        Some(t)
+    }
+
+    pub(crate) fn remove(&mut self, tx: u32) {
+        self.index.remove(&tx);
+    }
+
+    /// Forgets the oldest transaction, if there are more than `max`. Returns its tx
+    pub(crate) fn drop_oldest(&mut self, max: usize) -> Option<u32> {
+        while self.index.len() > max {
+            let (tx, offset) = self.order.pop_front()?;
+            if self.index.get(&tx).is_some_and(|(o, _)| *o == offset) {
+                self.index.remove(&tx);
+                return Some(tx)
+            }
+        }
+        None
+    }
+}
+
+/// Set of ids kept as ranges, so ids in a row take a single entry. Holds at most `max` ids, the oldest inserted ones
+/// are forgotten first
+#[derive(Debug)]
+pub(crate) struct IdSet {
+    /// Inclusive end per start
+    ranges: BTreeMap<u32, u32>,
+    /// Inserted ids, oldest first. The removed ones are stale
+    order: VecDeque<u32>,
+    max: usize,
+}
+
+impl IdSet {
+
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            ranges: BTreeMap::new(),
+            order: VecDeque::new(),
+            max,
+        }
+    }
+
+    /// Range the id is in
+    fn range(&self, id: u32) -> Option<(u32, u32)> {
+        self.ranges.range(..=id).next_back()
+            .map(|(&start, &end)| (start, end))
+            .filter(|&(_, end)| id <= end)
+    }
+
+    pub(crate) fn contains(&self, id: u32) -> bool {
+        self.range(id).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, id: u32) {
+        if self.contains(id) || self.max == 0 {
+            return
+        }
+
+        while self.order.len() >= self.max {
+            if let Some(oldest) = self.order.pop_front() {
+                self.remove(oldest);
+            }
+        }
+        self.order.push_back(id);
+
+        let mut start = id;
+        let mut end = id;
+        if let Some(before) = id.checked_sub(1).and_then(|before| self.range(before)) {
+            start = before.0;
+        }
+        if let Some(after) = id.checked_add(1).and_then(|after| self.ranges.remove(&after)) {
+            end = after;
+        }
+        self.ranges.insert(start, end);
+    }
+
+    pub(crate) fn remove(&mut self, id: u32) {
+        if let Some((start, end)) = self.range(id) {
+            self.ranges.remove(&start);
+            if start < id {
+                self.ranges.insert(start, id - 1);
+            }
+            if id < end {
+                self.ranges.insert(id + 1, end);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_ranges() {
+        let mut ids = IdSet::new(usize::MAX);
+        for id in [1, 2, 3, 5, 4, 10, u32::MAX, 0] {
+            ids.insert(id);
+        }
+        assert_eq!(ids.ranges.len(), 3);
+        assert!(ids.contains(0) && ids.contains(5) && ids.contains(u32::MAX));
+        assert!(!ids.contains(6) && !ids.contains(9));
+
+        ids.remove(3);
+        assert!(!ids.contains(3));
+        assert!(ids.contains(2) && ids.contains(4));
+        ids.remove(10);
+        assert_eq!(ids.ranges.len(), 3);
+    }
+
+    #[test]
+    fn compacts_the_spill() {
+        let dir = std::env::temp_dir().join(format!("case-compact-{}", std::process::id()));
+        let mut spill = Spill::new(dir.join("1.jsonl"));
+        let deposit = |tx| Transaction::new(TransactionType::Deposit, 1, tx, Some(Monetary::from(tx)), false);
+
+        for tx in 0..COMPACT_LINES as u32 * 3 {
+            spill.put(&deposit(tx)).unwrap();
+            spill.drop_oldest(10);
+            // Taken back and spilled again, a new line each time
+            spill.take(tx).unwrap();
+            spill.put(&deposit(tx)).unwrap();
+        }
+
+        let lines = fs::read_to_string(dir.join("1.jsonl")).unwrap().lines().count();
+        assert_eq!(lines, spill.lines);
+        assert!(lines <= COMPACT_LINES + 1);
+        assert_eq!(spill.index.len(), 10);
+        let last = COMPACT_LINES as u32 * 3 - 1;
+        assert_eq!(spill.get(last).unwrap().amount(), Some(Monetary::from(last)));
+        assert!(spill.get(last - 9).is_some() && spill.get(last - 10).is_none());
+        assert!(!dir.join("1.jsonl.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn forgets_the_oldest_ids() {
+        let mut ids = IdSet::new(3);
+        // Interleaved with the ids of other accounts, every one is a range of its own
+        for id in [10, 20, 30, 40, 50] {
+            ids.insert(id);
+        }
+        assert_eq!((ids.ranges.len(), ids.order.len()), (3, 3));
+        assert!(!ids.contains(10) && !ids.contains(20));
+        assert!(ids.contains(30) && ids.contains(50));
+
+        // Removed ids are stale in the order, they don't take the place of the others for long
+        ids.remove(30);
+        ids.insert(60);
+        assert!(ids.contains(40) && ids.contains(50) && ids.contains(60));
+        assert_eq!(ids.order.len(), 3);
+    }
+}
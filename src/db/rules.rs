@@ -0,0 +1,220 @@
+use serde::Deserialize;
+
+use rust_decimal_macros::dec;
+
+use std::fmt;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::db::transaction::TransactionType;
+use crate::Monetary;
+
+
+/// Suspicious pattern noticed in the activity of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Flag {
+    /// Too many disputes in a short window
+    DisputeBurst,
+    /// Withdrawal or outgoing transfer of most of the balance right after a deposit
+    DrainAfterDeposit,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Flag::DisputeBurst => write!(f, "dispute_burst"),
+            Flag::DrainAfterDeposit => write!(f, "drain_after_deposit"),
+        }
+    }
+}
+
+/// `disputes` or more disputes of the client within `window_ms` milliseconds
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct DisputeBurst {
+    pub window_ms: u64,
+    pub disputes: usize,
+}
+
+/// Configuration of the fraud rules. Every rule is optional.
+/// Windows look at the client's own successful transactions, by their timestamps, or the time of the `Clock` without them
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Rules {
+    pub dispute_burst: Option<DisputeBurst>,
+    /// Share of the available funds (in percents), which is suspicious to take right after a deposit
+    pub drain_after_deposit: Option<Monetary>,
+    /// Locks the account as soon as it's flagged
+    pub auto_lock: bool,
+}
+
+impl Rules {
+
+    /// Returns `true` if no rules are configured
+    pub fn is_empty(&self) -> bool {
+        self.dispute_burst.is_none() && self.drain_after_deposit.is_none()
+    }
+}
+
+/// Returns `true` if a transaction made at `time` is in the window of the one made at `now`.
+/// Later ones are in it as well, the input could be out of order
+fn within(time: u64, window: u64, now: u64) -> bool {
+    time.saturating_add(window) > now
+}
+
+/// Flag raised by a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Raised {
+    pub flag: Flag,
+    pub tx: u32,
+}
+
+/// Transaction as the rules see it
+#[derive(Debug, Clone)]
+pub struct Activity {
+    pub r#type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Monetary>,
+    /// Available funds of the client in the asset of the transaction, before it
+    pub available: Monetary,
+    /// Timestamp of the transaction, or the time of the `Clock`
+    pub time: u64,
+}
+
+/// Recent successful transactions of a client, only what the rules need
+#[derive(Debug, Default)]
+struct Recent {
+    /// Type of the last one
+    last: Option<TransactionType>,
+    /// Times of the latest disputes, at most one less than a burst
+    disputes: VecDeque<u64>,
+}
+
+/// Watches the transactions and raises flags on the suspicious patterns
+#[derive(Debug, Default)]
+pub struct Monitor {
+    rules: Rules,
+    /// Recent successful transactions per client
+    recent: HashMap<u16, Recent>,
+    /// Raised flags per client
+    flags: BTreeMap<u16, Vec<Raised>>,
+}
+
+impl Monitor {
+
+    /// Constructor
+    pub fn new(rules: &Rules) -> Self {
+        Self {
+            rules: rules.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns `true` if there is nothing to watch
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns `true` if the flagged accounts should be locked
+    pub fn auto_lock(&self) -> bool {
+        self.rules.auto_lock
+    }
+
+    /// Flags raised for the client
+    pub fn flags(&self, client: u16) -> &[Raised] {
+        self.flags.get(&client).map_or(&[], |flags| flags.as_slice())
+    }
+
+    /// Observes a transaction after it was processed, `ok` tells if it was applied.
+    /// Returns the newly raised flags
+    pub fn observe(&mut self, activity: &Activity, ok: bool) -> Vec<Flag> {
+        let recent = self.recent.entry(activity.client).or_default();
+        let mut raised = Vec::new();
+
+        if ok {
+            match activity.r#type {
+                TransactionType::Dispute => {
+                    if let Some(burst) = &self.rules.dispute_burst {
+                        let disputes = recent.disputes.iter().filter(|at| within(**at, burst.window_ms, activity.time)).count();
+                        if disputes + 1 >= burst.disputes {
+                            raised.push(Flag::DisputeBurst);
+                        }
+
+                        recent.disputes.push_back(activity.time);
+                        while recent.disputes.len() >= burst.disputes.max(1) {
+                            recent.disputes.pop_front();
+                        }
+                    }
+                },
+                TransactionType::Withdrawal | TransactionType::Transfer => {
+                    if let (Some(share), Some(amount)) = (self.rules.drain_after_deposit, activity.amount) {
+                        let after_deposit = recent.last == Some(TransactionType::Deposit);
+                        if after_deposit && activity.available > dec!(0) && amount * dec!(100) >= activity.available * share {
+                            raised.push(Flag::DrainAfterDeposit);
+                        }
+                    }
+                },
+                _ => {},
+            }
+
+            recent.last = Some(activity.r#type.clone());
+        }
+
+        if !raised.is_empty() {
+            self.flags.entry(activity.client).or_default()
+                .extend(raised.iter().map(|&flag| Raised { flag, tx: activity.tx }));
+        }
+
+        raised
+    }
+}
+
+/// Report of the raised flags, one per line
+impl fmt::Display for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "client, tx, flag")?;
+        for (client, flags) in &self.flags {
+            for raised in flags {
+                writeln!(f, "{}, {}, {}", client, raised.tx, raised.flag)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(r#type: TransactionType, tx: u32, amount: Option<Monetary>, available: Monetary, time: u64) -> Activity {
+        Activity { r#type, client: 1, tx, amount, available, time }
+    }
+
+    #[test]
+    fn patterns_are_flagged() {
+        let rules: Rules = serde_json::from_str(r#"{
+            "dispute_burst": {"window_ms": 1000, "disputes": 2},
+            "drain_after_deposit": 90
+        }"#).unwrap();
+        let mut monitor = Monitor::new(&rules);
+
+        assert!(monitor.observe(&activity(TransactionType::Deposit, 1, Some(dec!(100)), dec!(0), 0), true).is_empty());
+        assert_eq!(
+            monitor.observe(&activity(TransactionType::Withdrawal, 2, Some(dec!(95)), dec!(100), 10), true),
+            vec![Flag::DrainAfterDeposit]
+        );
+        // Not right after a deposit
+        assert!(monitor.observe(&activity(TransactionType::Withdrawal, 3, Some(dec!(5)), dec!(5), 20), true).is_empty());
+
+        assert!(monitor.observe(&activity(TransactionType::Dispute, 1, None, dec!(0), 1000), true).is_empty());
+        // A second later the first dispute is out of the window, however few transactions were between
+        assert!(monitor.observe(&activity(TransactionType::Dispute, 2, None, dec!(0), 2000), true).is_empty());
+        assert_eq!(monitor.observe(&activity(TransactionType::Dispute, 3, None, dec!(0), 2999), true), vec![Flag::DisputeBurst]);
+
+        assert_eq!(monitor.flags(1).len(), 2);
+        // Only what the rules need is kept
+        assert_eq!(monitor.recent[&1].disputes.len(), 1);
+        assert!(monitor.flags(2).is_empty());
+        assert_eq!(format!("{}", monitor).lines().nth(1), Some("1, 2, drain_after_deposit"));
+    }
+}
@@ -1,10 +1,31 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::Monetary;
 
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Asset of the transactions without the asset column
+pub const DEFAULT_ASSET: &str = "";
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Time of the transactions without a timestamp, for the limits and the fraud rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// Their arrival time, for a live stream
+    #[default]
+    Wall,
+    /// The latest timestamp seen before them, so the same input always gives the same result
+    Input,
+}
+
 
 /// Transaction types that are possible. Json values will be lowercase
-#[derive(Debug,Clone,Deserialize,Eq,PartialEq)]
+#[derive(Debug,Clone,Serialize,Deserialize,Eq,PartialEq,Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -12,6 +33,22 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves funds from the client to the `counterparty`
+    Transfer,
+}
+
+/// Same as the json and csv values
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionType::Deposit => write!(f, "deposit"),
+            TransactionType::Withdrawal => write!(f, "withdrawal"),
+            TransactionType::Dispute => write!(f, "dispute"),
+            TransactionType::Resolve => write!(f, "resolve"),
+            TransactionType::Chargeback => write!(f, "chargeback"),
+            TransactionType::Transfer => write!(f, "transfer"),
+        }
+    }
 }
 
 /// Represents a transaction, with extra field `subject_of_dispute`
@@ -21,10 +58,20 @@ pub struct Transaction {
     client: u16,
     tx: u32,
     amount: Option<Monetary>,
+    /// Currency or other asset of the transaction. Optional column, `DEFAULT_ASSET` if missing
+    asset: Option<String>,
+    /// Receiving client of a transfer. Optional column, used only by transfers
+    counterparty: Option<u16>,
+    /// Event time in milliseconds since the Unix epoch. Optional column
+    timestamp: Option<u64>,
     /// `subject_of_dispute` if the transaction is under a dispute
     /// Doesn't participate in any Serde activities
     #[serde(skip)]
     subject_of_dispute: bool,
+    /// Fee charged for the transaction, set by the engine
+    /// Doesn't participate in any Serde activities
+    #[serde(skip)]
+    fee: Option<Monetary>,
 }
 
 impl Transaction {
@@ -36,10 +83,32 @@ impl Transaction {
             client,
             tx,
             amount,
+            asset: None,
+            counterparty: None,
+            timestamp: None,
             subject_of_dispute,
+            fee: None,
         }
     }
 
+    /// Sets the receiving client of a transfer
+    pub fn with_counterparty(mut self, counterparty: u16) -> Self {
+        self.counterparty = Some(counterparty);
+        self
+    }
+
+    /// Sets the event time of the transaction
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the asset of the transaction
+    pub fn with_asset(mut self, asset: &str) -> Self {
+        self.asset = Some(asset.to_string());
+        self
+    }
+
     /// Starts a dispute for the transaction
     pub fn start_dispute(&mut self) {
         self.subject_of_dispute = true
@@ -55,6 +124,36 @@ impl Transaction {
         self.amount
     }
 
+    /// Amount setter
+    pub fn set_amount(&mut self, amount: Monetary) {
+        self.amount = Some(amount)
+    }
+
+    /// Asset getter, `DEFAULT_ASSET` if the transaction has none
+    pub fn asset(&self) -> &str {
+        self.asset.as_deref().unwrap_or(DEFAULT_ASSET)
+    }
+
+    /// Counterparty getter
+    pub fn counterparty(&self) -> Option<u16> {
+        self.counterparty
+    }
+
+    /// Timestamp getter
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Fee getter
+    pub fn fee(&self) -> Option<Monetary> {
+        self.fee
+    }
+
+    /// Fee setter
+    pub fn set_fee(&mut self, fee: Monetary) {
+        self.fee = Some(fee)
+    }
+
     /// Subject of dispute getter
     pub fn is_subject_of_dispute(&self) -> bool {
         self.subject_of_dispute
@@ -96,13 +195,18 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn client_tests() {
         let t = Transaction {
             r#type: TransactionType::Deposit,
             client: 1,
             tx: 1,
             amount: None,
+            asset: None,
+            counterparty: None,
+            timestamp: None,
             subject_of_dispute: false,
+            fee: None,
         };
         assert_eq!(t.has_client(2), false);
         assert_eq!(t.has_client(t.client()), true);
@@ -110,13 +214,18 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn dispute() {
         let mut t = Transaction {
             r#type: TransactionType::Deposit,
             client: 1,
             tx: 1,
             amount: None,
+            asset: None,
+            counterparty: None,
+            timestamp: None,
             subject_of_dispute: false,
+            fee: None,
         };
 
         assert_eq!(t.is_subject_of_dispute(), false);
@@ -1,10 +1,16 @@
 use rand::prelude::*;
+use rust_decimal::Decimal;
 
-pub fn rand_string(rng: &mut ThreadRng) -> String {
+use crate::db::transaction::{Transaction, TransactionType};
+use crate::wire;
+
+pub mod report;
+
+pub fn rand_string(rng: &mut impl Rng) -> String {
     (0..4).map(|_| rng.gen::<char>()).collect()
 }
 
-pub fn gen_spaces(rng: &mut ThreadRng) -> String {
+pub fn gen_spaces(rng: &mut impl Rng) -> String {
     let no_space: bool = rng.gen::<f64>() > 0.05;
     if no_space {
         "".to_string()
@@ -14,7 +20,7 @@ pub fn gen_spaces(rng: &mut ThreadRng) -> String {
     
 }
 
-pub fn gen_client(rng: &mut ThreadRng) -> String {
+pub fn gen_client(rng: &mut impl Rng) -> String {
     let correct: bool = rng.gen::<f64>() > 0.01;
     if correct {
         // rng.gen::<u16>().to_string()
@@ -24,7 +30,7 @@ pub fn gen_client(rng: &mut ThreadRng) -> String {
     }
 }
 
-pub fn gen_tx(rng: &mut ThreadRng) -> String {
+pub fn gen_tx(rng: &mut impl Rng) -> String {
     let correct: bool = rng.gen::<f64>() > 0.01;
     if correct {
         rng.gen_range(0..=1000).to_string()
@@ -34,7 +40,7 @@ pub fn gen_tx(rng: &mut ThreadRng) -> String {
     }
 }
 
-pub fn gen_money(rng: &mut ThreadRng) -> String {
+pub fn gen_money(rng: &mut impl Rng) -> String {
     let correct: bool = rng.gen::<f64>() > 0.01;
     if correct {
         format!("{:.4}", rng.gen::<f64>() * rng.gen_range(0..2_000_000_000) as f64)
@@ -49,7 +55,7 @@ pub fn gen_money(rng: &mut ThreadRng) -> String {
     }
 }
 
-pub fn gen_type(rng: &mut ThreadRng) -> String {
+pub fn gen_type(rng: &mut impl Rng) -> String {
     let correct: bool = rng.gen::<f64>() > 0.01;
     if correct {
         match rng.gen_range(0..=41) {
@@ -65,7 +71,7 @@ pub fn gen_type(rng: &mut ThreadRng) -> String {
     }
 }
 
-pub fn gen_line(rng: &mut ThreadRng) -> String {
+pub fn gen_line(rng: &mut impl Rng) -> String {
     format!(
         "{}{}{},{}{}{},{}{}{},{}{}{}",
         gen_spaces(rng), gen_type(rng), gen_spaces(rng),
@@ -75,6 +81,30 @@ pub fn gen_line(rng: &mut ThreadRng) -> String {
     )
 }
 
-pub fn gen_json(rng: &mut ThreadRng) -> String {
+pub fn gen_json(rng: &mut impl Rng) -> String {
     format!("{{\"type\": \"{}\",\"client\": {},\"tx\": {},\"amount\": {}}}", gen_type(rng), gen_client(rng), gen_tx(rng), gen_money(rng))
-}
\ No newline at end of file
+}
+/// Binary frame of a transaction, see `wire`. Some are random bytes of a valid length, which the server can't decode
+pub fn gen_frame(rng: &mut impl Rng) -> Vec<u8> {
+    let correct: bool = rng.gen::<f64>() > 0.01;
+    if correct {
+        let r#type = match rng.gen_range(0..=81) {
+            0..=20 => TransactionType::Deposit,
+            21..=40 => TransactionType::Withdrawal,
+            41..=60 => TransactionType::Dispute,
+            61..=80 => TransactionType::Resolve,
+            _ => TransactionType::Chargeback,
+        };
+        let amount = match r#type {
+            TransactionType::Deposit | TransactionType::Withdrawal => Some(Decimal::new(rng.gen_range(0..20_000_000_000_000), 4)),
+            _ => None,
+        };
+        let t = Transaction::new(r#type, rng.gen_range(0..=1000), rng.gen_range(0..=1000), amount, false);
+        wire::encode(&t).expect("No asset, so it fits")
+    } else {
+        let len = rng.gen_range(0..=64u16);
+        let mut frame = len.to_le_bytes().to_vec();
+        frame.extend((0..len).map(|_| rng.gen::<u8>()));
+        frame
+    }
+}
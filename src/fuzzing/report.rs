@@ -0,0 +1,310 @@
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+
+/// Upper bounds (in microseconds) of the latency histogram buckets. The last bucket catches everything above.
+const BUCKETS_US: [u64; 16] = [
+    50, 100, 200, 300, 500, 750,
+    1_000, 2_000, 3_000, 5_000, 7_500,
+    10_000, 25_000, 50_000, 100_000, 1_000_000,
+];
+
+/// Collects latencies of the requests. Keeps every sample, so percentiles are exact.
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    samples: Vec<u64>,
+}
+
+impl Histogram {
+
+    /// Records a single latency
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency.as_micros() as u64)
+    }
+
+    /// Moves all the samples from another histogram
+    pub fn merge(&mut self, other: Histogram) {
+        self.samples.extend(other.samples)
+    }
+
+    /// Amount of recorded samples
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns `true` if nothing was recorded
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Summarizes the samples: percentiles, max, mean and buckets
+    pub fn summary(&self) -> LatencySummary {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+
+        let percentile = |p: f64| -> u64 {
+            if sorted.is_empty() {
+                return 0
+            }
+            let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        let mean = if sorted.is_empty() {
+            0.0
+        } else {
+            sorted.iter().sum::<u64>() as f64 / sorted.len() as f64
+        };
+
+        let mut buckets: Vec<Bucket> = BUCKETS_US.iter()
+            .map(|&le| Bucket { le_us: Some(le), count: 0 })
+            .chain(std::iter::once(Bucket { le_us: None, count: 0 }))
+            .collect();
+        for sample in &sorted {
+            let i = BUCKETS_US.iter().position(|le| sample <= le).unwrap_or(BUCKETS_US.len());
+            buckets[i].count += 1;
+        }
+
+        LatencySummary {
+            p50_us: percentile(50.0),
+            p95_us: percentile(95.0),
+            p99_us: percentile(99.0),
+            max_us: sorted.last().copied().unwrap_or(0),
+            mean_us: mean,
+            histogram: buckets,
+        }
+    }
+}
+
+/// Histogram bucket. `le_us` is `None` for the last, unbounded one
+#[derive(Debug, Clone, Serialize)]
+pub struct Bucket {
+    pub le_us: Option<u64>,
+    pub count: u64,
+}
+
+/// Latency part of the report
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    pub mean_us: f64,
+    pub histogram: Vec<Bucket>,
+}
+
+/// Classifies the body of the server's response, so similar responses are counted together.
+/// Numbers (like the allowed amount) are cut off.
+pub fn outcome_of(body: &str) -> String {
+    if body == "OK" {
+        "ok".to_string()
+    } else if body.starts_with("Err: ") {
+        let cut = body.find(|c: char| c.is_ascii_digit()).unwrap_or(body.len());
+        body[..cut].trim_end_matches(|c: char| c == ':' || c.is_whitespace()).to_string()
+    } else if body.starts_with("Error: ") {
+        "malformed".to_string()
+    } else if body == "Empty request" {
+        "empty".to_string()
+    } else {
+        "other".to_string()
+    }
+}
+
+/// Final outcome of a request, after all the retries
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    /// The server responded with the status and the body
+    Done(u16, String),
+    /// Every attempt timed out, or the last one did
+    TimedOut,
+    /// Connection or protocol error on the last attempt
+    Failed,
+}
+
+/// Statistics collected by a single worker. Workers' stats are merged into the final report.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub latencies: Histogram,
+    pub statuses: BTreeMap<String, u64>,
+    pub outcomes: BTreeMap<String, u64>,
+    pub failed: u64,
+    pub timed_out: u64,
+    pub retries: u64,
+    /// Scheduled requests not sent, because too many were in flight
+    pub skipped: u64,
+}
+
+impl Stats {
+
+    /// Records a finished request. Latency is recorded only for the requests the server answered.
+    pub fn record(&mut self, latency: Duration, outcome: Outcome, retries: u32) {
+        self.retries += retries as u64;
+
+        match outcome {
+            Outcome::Done(status, body) => {
+                self.latencies.record(latency);
+                *self.statuses.entry(status.to_string()).or_insert(0) += 1;
+                *self.outcomes.entry(outcome_of(&body)).or_insert(0) += 1;
+            },
+            Outcome::TimedOut => self.timed_out += 1,
+            Outcome::Failed => self.failed += 1,
+        }
+    }
+
+    /// Merges another worker's stats into this one
+    pub fn merge(&mut self, other: Stats) {
+        self.latencies.merge(other.latencies);
+        for (k, v) in other.statuses {
+            *self.statuses.entry(k).or_insert(0) += v;
+        }
+        for (k, v) in other.outcomes {
+            *self.outcomes.entry(k).or_insert(0) += v;
+        }
+        self.failed += other.failed;
+        self.timed_out += other.timed_out;
+        self.retries += other.retries;
+        self.skipped += other.skipped;
+    }
+
+    /// Amount of made requests, including failed and timed out
+    pub fn requests(&self) -> usize {
+        self.latencies.len() + self.failed as usize + self.timed_out as usize
+    }
+}
+
+/// Final report of a load run. Serialized into json, to compare runs between builds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub url: String,
+    /// `closed` (fixed amount of workers) or `open` (fixed request rate)
+    pub mode: String,
+    pub concurrency: u64,
+    /// Target rate in requests per second, for the open mode
+    pub target_rate: Option<f64>,
+    pub requests: usize,
+    pub elapsed_sec: f64,
+    pub throughput: f64,
+    pub latency: LatencySummary,
+    pub statuses: BTreeMap<String, u64>,
+    pub outcomes: BTreeMap<String, u64>,
+    /// Requests failed after all the retries
+    pub failed: u64,
+    /// Requests timed out after all the retries
+    pub timed_out: u64,
+    /// Total retries made
+    pub retries: u64,
+    /// Scheduled requests not sent in the open mode, because too many were in flight
+    pub skipped: u64,
+    /// The run was stopped by Ctrl-C, so the statistics are partial
+    pub interrupted: bool,
+}
+
+impl Report {
+
+    /// Constructor
+    pub fn new(url: &str, mode: &str, concurrency: u64, target_rate: Option<f64>, elapsed: Duration, stats: Stats) -> Self {
+        let elapsed_sec = elapsed.as_secs_f64();
+        let requests = stats.requests();
+
+        Self {
+            url: url.to_string(),
+            mode: mode.to_string(),
+            concurrency,
+            target_rate,
+            requests,
+            elapsed_sec,
+            throughput: if elapsed_sec > 0.0 { requests as f64 / elapsed_sec } else { 0.0 },
+            latency: stats.latencies.summary(),
+            statuses: stats.statuses,
+            outcomes: stats.outcomes,
+            failed: stats.failed,
+            timed_out: stats.timed_out,
+            retries: stats.retries,
+            skipped: stats.skipped,
+            interrupted: false,
+        }
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.interrupted {
+            writeln!(f, "Interrupted, the statistics are partial")?;
+        }
+        writeln!(f, "Done {} requests in {:.6} sec total, {:.1} req/sec. Mode {}, concurrency || {}",
+            self.requests, self.elapsed_sec, self.throughput, self.mode, self.concurrency)?;
+        writeln!(f, "Failed {}, timed out {}, retries {}, skipped {}", self.failed, self.timed_out, self.retries, self.skipped)?;
+        writeln!(f, "Latency: p50 {}us, p95 {}us, p99 {}us, max {}us, mean {:.1}us",
+            self.latency.p50_us, self.latency.p95_us, self.latency.p99_us, self.latency.max_us, self.latency.mean_us)?;
+        for (status, count) in &self.statuses {
+            writeln!(f, "Status {}: {}", status, count)?;
+        }
+        for (outcome, count) in &self.outcomes {
+            writeln!(f, "Outcome {}: {}", outcome, count)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut h = Histogram::default();
+        for i in 1..=100 {
+            h.record(Duration::from_micros(i));
+        }
+        let s = h.summary();
+
+        assert_eq!(s.p50_us, 50);
+        assert_eq!(s.p95_us, 95);
+        assert_eq!(s.p99_us, 99);
+        assert_eq!(s.max_us, 100);
+        assert_eq!(s.histogram[0].count, 50);
+        assert_eq!(s.histogram[1].count, 50);
+        assert_eq!(s.histogram.iter().map(|b| b.count).sum::<u64>(), 100);
+    }
+
+    #[test]
+    fn empty_histogram() {
+        let s = Histogram::default().summary();
+        assert_eq!(s.p99_us, 0);
+        assert_eq!(s.max_us, 0);
+    }
+
+    #[test]
+    fn failures_are_counted() {
+        let mut a = Stats::default();
+        a.record(Duration::from_micros(10), Outcome::Done(200, "OK".to_string()), 0);
+        a.record(Duration::from_micros(10), Outcome::TimedOut, 3);
+
+        let mut b = Stats::default();
+        b.record(Duration::from_micros(10), Outcome::Failed, 1);
+
+        a.merge(b);
+
+        assert_eq!(a.requests(), 3);
+        assert_eq!(a.latencies.len(), 1);
+        assert_eq!(a.timed_out, 1);
+        assert_eq!(a.failed, 1);
+        assert_eq!(a.retries, 4);
+        assert_eq!(a.outcomes.get("ok"), Some(&1));
+    }
+
+    #[test]
+    fn outcomes() {
+        assert_eq!(outcome_of("OK"), "ok");
+        assert_eq!(
+            outcome_of("Err: Error during processing the transaction by the account:Too much requested, maximum allowed: 15.5"),
+            "Err: Error during processing the transaction by the account:Too much requested, maximum allowed"
+        );
+        assert_eq!(outcome_of("Error: Deserialize { pos: None }"), "malformed");
+        assert_eq!(outcome_of("Empty request"), "empty");
+    }
+}
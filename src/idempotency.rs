@@ -0,0 +1,212 @@
+use serde::Deserialize;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+
+/// How long and how many responses the server remembers for the idempotency keys
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Idempotency {
+    /// Seconds a response is remembered
+    pub window_sec: u64,
+    /// Most keys remembered, the oldest ones are forgotten first. The keys of the requests still processed
+    /// aren't forgotten for it
+    pub max_keys: usize,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self {
+            window_sec: 3600,
+            max_keys: 1_000_000,
+        }
+    }
+}
+
+/// Remembered request and the response to it, `None` while it's processed
+struct Entry {
+    at: Instant,
+    fingerprint: String,
+    response: Option<String>,
+}
+
+/// Remembered outcome of a key
+#[derive(Debug, Clone, PartialEq)]
+pub enum Replay {
+    /// Response to the same request
+    Response(String),
+    /// The key was used for a different request
+    Mismatch,
+    /// The request with the key is still processed
+    InProgress,
+}
+
+/// Responses of the requests with idempotency keys, so a retried request gets the same response
+pub struct IdempotencyCache {
+    window: Duration,
+    max_keys: usize,
+    entries: HashMap<String, Entry>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl IdempotencyCache {
+
+    /// Constructor
+    pub fn new(config: &Idempotency) -> Self {
+        Self {
+            window: Duration::from_secs(config.window_sec),
+            max_keys: config.max_keys,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Response remembered for the key, if it's not expired
+    pub fn get(&self, key: &str, fingerprint: &str) -> Option<Replay> {
+        let entry = self.entries.get(key).filter(|entry| entry.at.elapsed() < self.window)?;
+
+        if entry.fingerprint != fingerprint {
+            Some(Replay::Mismatch)
+        } else {
+            match &entry.response {
+                Some(response) => Some(Replay::Response(response.clone())),
+                None => Some(Replay::InProgress),
+            }
+        }
+    }
+
+    /// Remembers the response for the key, forgetting the expired and the oldest keys
+    pub fn insert(&mut self, key: String, fingerprint: String, response: String) {
+        self.push(key, fingerprint, Some(response))
+    }
+
+    /// Same as `get`, but if there is nothing for the key, reserves it for the request till it's `complete`d,
+    /// so the cache doesn't have to be locked while the request is processed
+    pub fn reserve(&mut self, key: &str, fingerprint: &str) -> Option<Replay> {
+        let replay = self.get(key, fingerprint);
+        if replay.is_none() {
+            self.push(key.to_string(), fingerprint.to_string(), None);
+        }
+
+        replay
+    }
+
+    /// Remembers the response to the reserved key
+    pub fn complete(&mut self, key: &str, response: String) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.response.get_or_insert(response);
+        }
+    }
+
+    /// Forgets the reserved key, the request wasn't processed
+    pub fn release(&mut self, key: &str) {
+        if self.entries.get(key).is_some_and(|entry| entry.response.is_none()) {
+            self.entries.remove(key);
+        }
+    }
+
+    /// Remembers the entry, forgetting the expired and the oldest keys, except the ones still processed
+    fn push(&mut self, key: String, fingerprint: String, response: Option<String>) {
+        let now = Instant::now();
+        let mut processed = Vec::new();
+
+        while let Some((at, _)) = self.order.front() {
+            let expired = now.duration_since(*at) >= self.window;
+            if !expired && self.order.len() < self.max_keys {
+                break
+            }
+            if let Some((at, key)) = self.order.pop_front() {
+                // The key could be used again after it expired, then the entry is a newer one
+                match self.entries.get(&key).filter(|entry| entry.at == at) {
+                    Some(entry) if entry.response.is_none() && !expired => processed.push((at, key)),
+                    Some(_) => { self.entries.remove(&key); },
+                    None => {},
+                }
+            }
+        }
+        for kept in processed.into_iter().rev() {
+            self.order.push_front(kept);
+        }
+
+        if self.max_keys == 0 {
+            return
+        }
+
+        self.order.push_back((now, key.clone()));
+        self.entries.insert(key, Entry { at: now, fingerprint, response });
+    }
+
+    /// Amount of remembered keys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if nothing is remembered
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_and_forgets() {
+        let mut cache = IdempotencyCache::new(&Idempotency { window_sec: 3600, max_keys: 2 });
+
+        assert!(cache.get("a", "deposit 1").is_none());
+        cache.insert("a".to_string(), "deposit 1".to_string(), "OK".to_string());
+        assert_eq!(cache.get("a", "deposit 1"), Some(Replay::Response("OK".to_string())));
+        assert_eq!(cache.get("a", "deposit 2"), Some(Replay::Mismatch));
+
+        cache.insert("b".to_string(), "b".to_string(), "OK".to_string());
+        cache.insert("c".to_string(), "c".to_string(), "OK".to_string());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a", "deposit 1").is_none());
+
+        let mut cache = IdempotencyCache::new(&Idempotency { window_sec: 0, max_keys: 2 });
+        cache.insert("a".to_string(), "a".to_string(), "OK".to_string());
+        assert!(cache.get("a", "a").is_none());
+    }
+
+    #[test]
+    fn reserves() {
+        let mut cache = IdempotencyCache::new(&Idempotency::default());
+
+        assert!(cache.reserve("a", "deposit 1").is_none());
+        assert_eq!(cache.reserve("a", "deposit 1"), Some(Replay::InProgress));
+        assert_eq!(cache.reserve("a", "deposit 2"), Some(Replay::Mismatch));
+        cache.complete("a", "OK".to_string());
+        assert_eq!(cache.reserve("a", "deposit 1"), Some(Replay::Response("OK".to_string())));
+
+        assert!(cache.reserve("b", "b").is_none());
+        cache.release("b");
+        assert!(cache.get("b", "b").is_none());
+        // Completed ones stay
+        cache.release("a");
+        assert_eq!(cache.get("a", "deposit 1"), Some(Replay::Response("OK".to_string())));
+    }
+
+    #[test]
+    fn keeps_the_processed_keys() {
+        let mut cache = IdempotencyCache::new(&Idempotency { window_sec: 3600, max_keys: 1 });
+
+        assert!(cache.reserve("a", "a").is_none());
+        cache.insert("b".to_string(), "b".to_string(), "OK".to_string());
+        assert!(cache.reserve("c", "c").is_none());
+        assert_eq!(cache.get("a", "a"), Some(Replay::InProgress));
+        assert_eq!(cache.get("c", "c"), Some(Replay::InProgress));
+        assert!(cache.get("b", "b").is_none());
+
+        cache.complete("a", "OK".to_string());
+        assert_eq!(cache.get("a", "a"), Some(Replay::Response("OK".to_string())));
+        // Once completed, it's forgotten for the limit again
+        cache.complete("c", "OK".to_string());
+        cache.insert("d".to_string(), "d".to_string(), "OK".to_string());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("d", "d"), Some(Replay::Response("OK".to_string())));
+    }
+}
@@ -0,0 +1,138 @@
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::Path;
+
+
+/// Compression of an input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+
+    /// Detects the compression by the magic bytes of the file, by the extension if they tell nothing.
+    /// So a broken `.gz` file fails to decompress instead of being read as a plain one
+    pub fn detect(location: &Path, head: &[u8]) -> Self {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            return Compression::Gzip
+        }
+        if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Compression::Zstd
+        }
+
+        match location.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Opens an input file, decompressing `.gz` and `.zst` files transparently. Plain files are memory-mapped.
+/// The file shouldn't be changed while it's read
+pub fn open(location: &str) -> io::Result<Box<dyn Read + Send>> {
+    let path = Path::new(location);
+    let mut file = File::open(path)?;
+
+    let mut head = [0; 4];
+    let mut read = 0;
+    while read < head.len() {
+        match file.read(&mut head[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    // Empty files can't be mapped
+    if read == 0 {
+        return Ok(Box::new(io::empty()))
+    }
+
+    match Compression::detect(path, &head[..read]) {
+        Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(BufReader::new(File::open(path)?)))),
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(File::open(path)?)?)),
+        Compression::None => {
+            // Safety: the map is read-only, the file is expected not to be changed while it's read
+            let map = unsafe { Mmap::map(&file)? };
+            Ok(Box::new(Cursor::new(map)))
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn read(location: &str) -> String {
+        let mut content = String::new();
+        open(location).unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn reads_compressed_and_plain() {
+        let dir = std::env::temp_dir().join(format!("case-input-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let plain = dir.join("plain.csv");
+        std::fs::write(&plain, CSV).unwrap();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(CSV.as_bytes()).unwrap();
+        // Detected by the magic bytes, not by the extension
+        let gzip = dir.join("gzip.csv");
+        std::fs::write(&gzip, gz.finish().unwrap()).unwrap();
+
+        let zst = dir.join("zstd.csv.zst");
+        std::fs::write(&zst, zstd::stream::encode_all(CSV.as_bytes(), 0).unwrap()).unwrap();
+
+        let empty = dir.join("empty.csv");
+        std::fs::write(&empty, "").unwrap();
+
+        for file in [&plain, &gzip, &zst] {
+            assert_eq!(read(file.to_str().unwrap()), CSV);
+        }
+        assert_eq!(read(empty.to_str().unwrap()), "");
+        assert_eq!(Compression::detect(Path::new("a.csv.gz"), b"type"), Compression::Gzip);
+        assert_eq!(Compression::detect(Path::new("a.csv"), b"type"), Compression::None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_gzip_fails() {
+        let dir = std::env::temp_dir().join(format!("case-input-truncated-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        writeln!(gz, "type,client,tx,amount").unwrap();
+        for tx in 0..10_000 {
+            writeln!(gz, "deposit,{},{},{}.0", tx % 100, tx, tx).unwrap();
+        }
+        let compressed = gz.finish().unwrap();
+        let truncated = dir.join("truncated.csv.gz");
+        // Cut in the middle of the records
+        std::fs::write(&truncated, &compressed[..compressed.len() / 2]).unwrap();
+        let location = truncated.to_str().unwrap();
+
+        assert!(open(location).unwrap().read_to_end(&mut Vec::new()).is_err());
+        // Not skipped like a bad record, the whole input fails
+        match crate::db_from_file(location, &Default::default(), false) {
+            Err(e) => assert!(e.to_string().starts_with(location), "{}", e),
+            Ok(_) => panic!("a truncated file is read"),
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
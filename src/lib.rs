@@ -2,11 +2,31 @@ use rust_decimal::prelude::*;
 
 pub mod db;
 pub mod fuzzing;
+pub mod config;
+pub mod reorder;
+pub mod idempotency;
+pub mod parse;
+pub mod input;
+pub mod wire;
+pub mod lines;
+pub mod webhooks;
+pub mod metrics;
 
-use fuzzing::{gen_json, gen_line};
+use fuzzing::{gen_frame, gen_json, gen_line};
+use fuzzing::report::{Report, Stats, Outcome};
 
 use db::Db;
-use db::transaction::Transaction;
+use db::events::AccountEvent;
+use db::account::ledger::{AsOf, HISTORY_HEADER};
+use config::Config;
+use db::transaction::{Clock, Transaction};
+use reorder::Reorder;
+use idempotency::{IdempotencyCache, Replay};
+use parse::Transactions;
+use wire::{Frames, Status};
+use lines::{LineLimits, LineTooLong, Lines};
+use webhooks::Notifier;
+use metrics::SharedDb;
 
 /// Main type to deal with money, which is basically a Decimal
 type Monetary = Decimal;
@@ -14,121 +34,644 @@ type Monetary = Decimal;
 use std::io;
 use std::fs::File;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::BTreeSet;
 use warp::Filter;
 use csv::{Reader, StringRecord};
 
 
+use std::time::{Duration, Instant};
+use std::net::SocketAddr;
+use std::future::Future;
+use std::convert::Infallible;
+use tokio::sync::mpsc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::sync::broadcast::{self, error::RecvError};
+
 use hyper::{Body, Method, Request, Client};
+use hyper::client::HttpConnector;
 use futures::future::join_all;
+use futures::{FutureExt, Stream, StreamExt};
+use futures::future::OptionFuture;
 use chrono::prelude::*;
 
-/// Read lines from stdin and pass to the engine.
-pub fn from_stdin(verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut db = Db::default();
-    
-    let mut rdr = csv::ReaderBuilder::new()
+/// Passes every record of a csv reader to the engine and returns the resulting state.
+/// Records are parsed by the fast path of `parse::Transactions`.
+/// With `reorder_window` configured, the records are reordered by their timestamps first.
+/// Bad records are skipped, a failure to read stops the processing
+pub fn process_reader<R: io::Read>(reader: R, config: &Config, verbose: bool) -> Result<Db, Box<dyn std::error::Error + Send + Sync>> {
+    process_readers(vec![reader], config, verbose)
+}
+
+/// Passes every record of the csv readers, one after another, to the same engine and returns the resulting state.
+/// Every reader has its own header. The reordering goes across the readers
+pub fn process_readers<R: io::Read>(readers: Vec<R>, config: &Config, verbose: bool) -> Result<Db, Box<dyn std::error::Error + Send + Sync>> {
+    process_inputs(readers.into_iter().map(|reader| (None, reader)).collect(), config, verbose)
+}
+
+/// Same as `process_readers`, the errors of the named inputs are prefixed by their names
+fn process_inputs<R: io::Read>(inputs: Vec<(Option<&str>, R)>, config: &Config, verbose: bool) -> Result<Db, Box<dyn std::error::Error + Send + Sync>> {
+    let mut db = Db::new(config).with_clock(Clock::Input);
+    let mut reorder = config.reorder_window.map(Reorder::new);
+
+    for (name, reader) in inputs {
+        read_into(&mut db, &mut reorder, reader, verbose).map_err(|e| match name {
+            Some(name) => format!("{}: {}", name, e).into(),
+            None => Box::new(e) as Box<dyn std::error::Error + Send + Sync>,
+        })?;
+    }
+
+    if let Some(mut reorder) = reorder {
+        for t in reorder.flush() {
+            apply(&mut db, t, verbose);
+        }
+        if verbose {println!("Late transactions: {}", reorder.late())}
+    }
+
+    Ok(db)
+}
+
+/// Passes every record of a csv reader to the engine, through the reordering if it's set.
+/// Bad records are skipped, a failure to read (e.g. a broken compressed file) is returned
+fn read_into<R: io::Read>(db: &mut Db, reorder: &mut Option<Reorder>, reader: R, verbose: bool) -> Result<(), csv::Error> {
+    let rdr = csv::ReaderBuilder::new()
         .delimiter(b',')
         .trim(csv::Trim::All)
-        .from_reader(io::stdin());
+        .from_reader(reader);
 
-    for result in rdr.deserialize::<Transaction>() {
+    for result in Transactions::new(rdr) {
         match result {
             Ok(record) => {
                 if verbose {println!("{:?}", record)}
-                if let Err(e) = db.process_new_transaction(record) {
-                    if verbose {println!("E: {:?}", e)}
+                match reorder.as_mut() {
+                    Some(reorder) => {
+                        for t in reorder.push(record) {
+                            apply(db, t, verbose);
+                        }
+                    },
+                    None => apply(db, record, verbose),
                 }
             },
+            Err(e) if matches!(e.kind(), csv::ErrorKind::Io(_)) => return Err(e),
             Err(e) => {
                 if verbose {println!("E: {:?}", e)}
             },
         }
     }
 
+    Ok(())
+}
+
+/// Applies a single transaction, the errors are only printed in the verbose mode
+fn apply(db: &mut Db, t: Transaction, verbose: bool) {
+    if let Err(e) = db.process_new_transaction(t) {
+        if verbose {println!("E: {:?}", e)}
+    }
+}
+
+/// Read lines from stdin and pass to the engine.
+pub fn from_stdin(config: &Config, verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let db = process_reader(io::stdin(), config, verbose)?;
+
     println!("{}", db);
     Ok(())
 }
 
+/// Reads the file and returns the state of the engine after all the transactions.
+pub fn db_from_file(location: &str, config: &Config, verbose: bool) -> Result<Db, Box<dyn std::error::Error + Send + Sync>> {
+    db_from_files(&[location], config, verbose)
+}
+
+/// Reads the files in sequence into the same engine and returns its state after all the transactions.
+/// Compressed files are decompressed, plain ones are memory-mapped. Every file is opened before the processing starts
+pub fn db_from_files(locations: &[&str], config: &Config, verbose: bool) -> Result<Db, Box<dyn std::error::Error + Send + Sync>> {
+    let inputs = locations.iter()
+        .map(|location| input::open(location).map(|reader| (Some(*location), reader)).map_err(|e| format!("{}: {}", location, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    process_inputs(inputs, config, verbose)
+}
+
+/// Writes the report of the flagged accounts into the file
+pub fn write_flag_report(db: &Db, location: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    std::fs::write(location, db.monitor().to_string())?;
+
+    Ok(())
+}
+
+/// Writes the trial balance of the journal into the file
+pub fn write_trial_balance(db: &Db, location: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    std::fs::write(location, db.trial_balance().to_string())?;
+
+    Ok(())
+}
+
 /// Read lines from file and pass to the engine.
-pub fn from_file(location: &str, verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut db = Db::default();
-    
-    let file = File::open(location)?;
+pub fn from_file(location: &str, config: &Config, verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    from_files(&[location], config, verbose)
+}
 
-    let mut rdr = csv::ReaderBuilder::new()
-        .delimiter(b',')
-        .trim(csv::Trim::All)
-        .from_reader(file);
+/// Read lines from the files and pass to the engine.
+pub fn from_files(locations: &[&str], config: &Config, verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let db = db_from_files(locations, config, verbose)?;
 
-    for result in rdr.deserialize::<Transaction>() {
-        match result {
-            Ok(record) => {
-                if verbose {println!("{:?}", record)}
-                if let Err(e) = db.process_new_transaction(record) {
+    println!("{}", db);
+    Ok(())
+}
+
+
+/// Columns of a csv line sent to the server. `asset`, `counterparty` and `timestamp` are optional
+const CSV_COLUMNS: [&str; 7] = ["type", "client", "tx", "amount", "asset", "counterparty", "timestamp"];
+
+/// Parses a single csv line without a header, the way the server does it. The optional columns are picked by the amount of fields.
+/// Returns `None` if there is nothing to parse
+pub fn parse_csv_line(line: &[u8]) -> Option<Result<Transaction, csv::Error>> {
+    let fields = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(line)
+        .records()
+        .next()
+        .and_then(|record| record.ok())
+        .map_or(0, |record| record.len());
+
+    let mut rdr = Reader::from_reader(line);
+
+    rdr.set_headers(StringRecord::from(CSV_COLUMNS[..fields.clamp(4, CSV_COLUMNS.len())].to_vec()));
+
+    rdr.deserialize::<Transaction>().next()
+}
+
+/// Events buffered for every `/events` subscriber. A slower one skips the oldest and gets a `lagged` event
+const EVENTS_CAPACITY: usize = 4096;
+
+/// Query of the events endpoint, only the events of the client if it's set
+#[derive(Debug, serde::Deserialize)]
+struct EventsQuery {
+    client: Option<u16>,
+}
+
+/// Events of the engine as server-sent events: `account` with the json of the event,
+/// or `lagged` with the amount of the skipped ones, if the subscriber couldn't keep up
+fn sse_events(events: broadcast::Receiver<AccountEvent>, client: Option<u16>) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> {
+    futures::stream::unfold(events, move |mut events| async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) if client.is_none_or(|client| client == event.client) => {
+                    warp::sse::Event::default()
+                        .event("account")
+                        .json_data(&event)
+                        .expect("Event is serializable")
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warp::sse::Event::default()
+                        .event("lagged")
+                        .data(skipped.to_string())
+                },
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), events))
+        }
+    })
+}
+
+/// Query of the balances endpoint: either the transaction or the time (milliseconds since the Unix epoch)
+#[derive(Debug, serde::Deserialize)]
+struct AsOfQuery {
+    tx: Option<u32>,
+    /// Client of the `tx`, the account's own by default. Transfers and fees are recorded under the client who made them
+    client: Option<u16>,
+    at: Option<u64>,
+}
+
+/// History of the balance changes of the client, as it's returned by the server
+pub fn format_history(db: &Db, client: u16) -> String {
+    match db.history(client) {
+        Some(history) => {
+            let mut result = format!("{}\n", HISTORY_HEADER);
+            for entry in history {
+                result.push_str(&entry.format(&db.precision().for_asset(&entry.asset)));
+            }
+            result
+        },
+        None => "Account not found".to_string(),
+    }
+}
+
+/// Balances of the client at the point of the history, as they are returned by the server
+pub fn format_balances_as_of(db: &Db, client: u16, point: AsOf) -> String {
+    match db.balances_as_of(client, point) {
+        Some(balances) => {
+            let mut result = "client, asset, available, held, total\n".to_string();
+            for (asset, balance) in balances {
+                let precision = db.precision().for_asset(&asset);
+                result.push_str(&format!("{}, {}, {}, {}, {}\n",
+                    client, asset, precision.format(balance.available), precision.format(balance.held), precision.format(balance.total())));
+            }
+            result
+        },
+        None => "Not found in the history".to_string(),
+    }
+}
+
+/// Passes the transaction to the engine and returns the response. With an idempotency key, the response is remembered,
+/// and a retry with the same key gets it again instead of being processed twice. The key is reserved while the transaction
+/// is processed, the cache isn't locked meanwhile
+fn submit(db: &SharedDb, cache: &Mutex<IdempotencyCache>, key: Option<String>, transaction: Transaction) -> String {
+    let key = match key {
+        Some(key) => key,
+        None => return process(db, transaction),
+    };
+
+    let fingerprint = format!("{:?}", transaction);
+    let replay = match cache.lock() {
+        Ok(mut cache) => cache.reserve(&key, &fingerprint),
+        Err(e) => return format!("poison error: {}", e),
+    };
+
+    match replay {
+        Some(Replay::Response(response)) => response,
+        Some(Replay::Mismatch) => "Err: Idempotency key was used for a different request".to_string(),
+        Some(Replay::InProgress) => "Err: Request with the idempotency key is still processed".to_string(),
+        None => {
+            let response = process(db, transaction);
+            // The transaction is processed already, so its response is returned anyway
+            if let Ok(mut cache) = cache.lock() {
+                if response.starts_with("poison error") {
+                    cache.release(&key);
+                } else {
+                    cache.complete(&key, response.clone());
+                }
+            }
+            response
+        },
+    }
+}
+
+/// Passes the transaction to the engine and returns the response
+fn process(db: &SharedDb, transaction: Transaction) -> String {
+    let metrics = &db.metrics;
+    match db.lock() {
+        Ok(mut db) => {
+            let r#type = transaction.get_type().clone();
+            let result = db.process_new_transaction(transaction);
+            metrics.record_transaction(r#type, &result);
+            match result {
+                Ok(_) => "OK".to_string(),
+                Err(e) => format!("Err: {}", e),
+            }
+        },
+        Err(e) => format!("poison error: {}", e)
+    }
+}
+
+/// Run server. Post is passed to the engine. Get fetches the actual state.
+/// The raw TCP listeners, if any, are bound on the same host and share the engine.
+pub async fn run_server(port: u16, listeners: Listeners, config: &Config, verbose: bool) {
+    let (_, _, server) = bind_server_with(([127, 0, 0, 1], port), listeners, config, verbose);
+
+    server.await
+}
+
+/// Binds the server to the address, port `0` picks a free one. Returns the actual address and the server itself,
+/// which runs when awaited. Panics if the address can't be bound.
+pub fn bind_server(addr: impl Into<SocketAddr>, config: &Config, verbose: bool) -> (SocketAddr, impl Future<Output = ()>) {
+    let (db, events, webhooks) = server_state(config);
+    let (addr, server) = bind_routes(addr, db, events, config, verbose);
+
+    (addr, async move {
+        futures::join!(server, OptionFuture::from(webhooks));
+    })
+}
+
+/// Engine of the server, pushing its events into the channel of the `/events` subscribers and to the webhooks.
+/// The delivery of the webhooks, if any are configured, runs when awaited
+fn server_state(config: &Config) -> (Arc<SharedDb>, broadcast::Sender<AccountEvent>, Option<impl Future<Output = ()>>) {
+    let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+    let (notifier, delivery) = if config.webhooks.is_empty() {
+        (None, None)
+    } else {
+        let (notifier, delivery) = Notifier::new(&config.webhooks);
+        (Some(notifier), Some(delivery))
+    };
+
+    let sender = events.clone();
+    let db = Db::new(config).with_events(move |event| {
+        if let Some(notifier) = &notifier {
+            notifier.notify(&event);
+        }
+        // No subscribers is not an error
+        let _ = sender.send(event);
+    });
+
+    (Arc::new(SharedDb::new(db)), events, delivery)
+}
+
+/// Addresses of the raw TCP listeners, which run next to the http server. Port `0` picks a free one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Listeners {
+    /// Binary frames, see `wire`
+    pub wire: Option<SocketAddr>,
+    /// Newline-delimited csv or json transactions, see `lines`
+    pub lines: Option<SocketAddr>,
+}
+
+/// Same as `bind_server`, plus the raw TCP listeners, sharing the engine. Returns the actual addresses of all of them.
+/// Panics if any address can't be bound.
+pub fn bind_server_with(addr: impl Into<SocketAddr>, listeners: Listeners, config: &Config, verbose: bool) -> (SocketAddr, Listeners, impl Future<Output = ()>) {
+    let (db, events, webhooks) = server_state(config);
+    let mut bound = Listeners::default();
+    let mut servers = Vec::new();
+
+    if let Some(webhooks) = webhooks {
+        servers.push(webhooks.boxed());
+    }
+
+    if let Some(wire_addr) = listeners.wire {
+        let (wire_addr, wire) = bind_wire(wire_addr, db.clone(), verbose);
+        bound.wire = Some(wire_addr);
+        servers.push(wire.boxed());
+    }
+    if let Some(lines_addr) = listeners.lines {
+        let (lines_addr, lines) = bind_lines(lines_addr, db.clone(), config.lines, verbose);
+        bound.lines = Some(lines_addr);
+        servers.push(lines.boxed());
+    }
+
+    let (addr, server) = bind_routes(addr.into(), db, events, config, verbose);
+    servers.push(server.boxed());
+
+    (addr, bound, async move {
+        join_all(servers).await;
+    })
+}
+
+/// Binds a std listener, which is turned into the tokio one inside of the runtime
+fn bind_tcp(addr: SocketAddr) -> (SocketAddr, std::net::TcpListener) {
+    let listener = std::net::TcpListener::bind(addr).expect("Listener address should be free");
+    listener.set_nonblocking(true).expect("Listener should switch to the non-blocking mode");
+    let addr = listener.local_addr().expect("Listener is bound");
+
+    (addr, listener)
+}
+
+/// Binds the raw TCP listener of binary frames. Every connection is a stream of frames, a status byte is written back
+/// for every frame in order. Clients should read the statuses while writing, or both sides stall on full buffers.
+/// Panics if the address can't be bound.
+pub fn bind_wire(addr: impl Into<SocketAddr>, db: Arc<SharedDb>, verbose: bool) -> (SocketAddr, impl Future<Output = ()>) {
+    let (addr, listener) = bind_tcp(addr.into());
+
+    (addr, async move {
+        let listener = tokio::net::TcpListener::from_std(listener).expect("Runs inside of the runtime");
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(serve_wire_connection(socket, db.clone(), verbose));
+                },
+                Err(e) => {
                     if verbose {println!("E: {:?}", e)}
+                },
+            }
+        }
+    })
+}
+
+/// Binds the raw TCP listener of newline-delimited csv or json transactions. Lines of every connection are applied
+/// in order, a response line is written back for every one, the same as the http response to it would be.
+/// Connections above `limits.max_connections` get an error line and are closed. Panics if the address can't be bound.
+pub fn bind_lines(addr: impl Into<SocketAddr>, db: Arc<SharedDb>, limits: LineLimits, verbose: bool) -> (SocketAddr, impl Future<Output = ()>) {
+    let (addr, listener) = bind_tcp(addr.into());
+    let connections = Arc::new(Semaphore::new(limits.max_connections));
+
+    (addr, async move {
+        let listener = tokio::net::TcpListener::from_std(listener).expect("Runs inside of the runtime");
+        loop {
+            match listener.accept().await {
+                Ok((mut socket, _)) => {
+                    match connections.clone().try_acquire_owned() {
+                        Ok(permit) => {
+                            let db = db.clone();
+                            tokio::spawn(async move {
+                                serve_lines_connection(socket, db, limits.max_line, verbose).await;
+                                drop(permit);
+                            });
+                        },
+                        Err(_) => {
+                            tokio::spawn(async move {
+                                let _ = socket.write_all(b"Err: Too many connections\n").await;
+                            });
+                        },
+                    }
+                },
+                Err(e) => {
+                    if verbose {println!("E: {:?}", e)}
+                },
+            }
+        }
+    })
+}
+
+/// Reads lines of the connection until it's closed, writing back the responses
+async fn serve_lines_connection(mut socket: TcpStream, db: Arc<SharedDb>, max_line: usize, verbose: bool) {
+    let mut lines = Lines::new(max_line);
+    let mut chunk = vec![0; 64 * 1024];
+    let mut responses = String::new();
+
+    loop {
+        let n = match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        lines.push(&chunk[..n]);
+        while let Some(line) = lines.next_line() {
+            responses.push_str(&process_line(&db, line, verbose));
+            responses.push('\n');
+        }
+        if socket.write_all(responses.as_bytes()).await.is_err() {
+            return
+        }
+        responses.clear();
+    }
+
+    if let Some(line) = lines.finish() {
+        responses.push_str(&process_line(&db, line, verbose));
+        responses.push('\n');
+        let _ = socket.write_all(responses.as_bytes()).await;
+    }
+}
+
+/// Passes a single json or csv line to the engine and returns the response
+fn process_line(db: &SharedDb, line: Result<&[u8], LineTooLong>, verbose: bool) -> String {
+    let line = match line {
+        Ok(line) => line,
+        Err(e) => return format!("Err: {}", e),
+    };
+
+    let record = if line.trim_ascii_start().starts_with(b"{") {
+        serde_json::from_slice::<Transaction>(line).map_err(|e| format!("Error: {}", e))
+    } else {
+        match parse_csv_line(line) {
+            Some(record) => record.map_err(|e| format!("Error: {:?}", e)),
+            None => return "Empty request".to_string(),
+        }
+    };
+
+    match record {
+        Ok(transaction) => {
+            if verbose {println!("{:?}", transaction)}
+            process(db, transaction)
+        },
+        Err(e) => e,
+    }
+}
+
+/// Reads frames of the connection until it's closed, writing back the statuses
+async fn serve_wire_connection(mut socket: TcpStream, db: Arc<SharedDb>, verbose: bool) {
+    let mut frames = Frames::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let mut statuses = Vec::new();
+
+    loop {
+        let n = match socket.read(&mut chunk).await {
+            Ok(0) => {
+                // The stream was cut in the middle of a frame
+                if frames.pending() > 0 {
+                    let _ = socket.write_all(&[Status::Malformed as u8]).await;
                 }
+                break
             },
-            Err(e) => {
-                if verbose {println!("E: {:?}", e)}
-            },
+            Err(_) => break,
+            Ok(n) => n,
+        };
+        frames.push(&chunk[..n]);
+        apply_frames(&db, &mut frames, &mut statuses, verbose);
+        if socket.write_all(&statuses).await.is_err() {
+            break
         }
+        statuses.clear();
     }
+}
 
-    println!("{}", db);
-    Ok(())
+/// Reads frames of a streamed http body and streams back a status byte for every frame, as soon as it's applied.
+/// An incomplete frame at the end gets a `Malformed` status. An error of the body ends the response with the error
+fn stream_frames<S, B>(body: S, db: Arc<SharedDb>, verbose: bool) -> impl Stream<Item = Result<Vec<u8>, io::Error>>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: bytes::Buf,
+{
+    futures::stream::unfold(Some((Box::pin(body), Frames::new())), move |state| {
+        let db = db.clone();
+        async move {
+            let (mut body, mut frames) = state?;
+
+            loop {
+                match body.next().await {
+                    Some(Ok(mut chunk)) => {
+                        while chunk.has_remaining() {
+                            let part = chunk.chunk();
+                            let n = part.len();
+                            frames.push(part);
+                            chunk.advance(n);
+                        }
+
+                        let mut statuses = Vec::new();
+                        apply_frames(&db, &mut frames, &mut statuses, verbose);
+                        if !statuses.is_empty() {
+                            return Some((Ok(statuses), Some((body, frames))))
+                        }
+                    },
+                    Some(Err(e)) => {
+                        if verbose {println!("E: {:?}", e)}
+                        return Some((Err(io::Error::other(e)), None))
+                    },
+                    None if frames.pending() > 0 => return Some((Ok(vec![Status::Malformed as u8]), None)),
+                    None => return None,
+                }
+            }
+        }
+    })
 }
 
+/// Passes the complete frames to the engine under a single lock and appends a status byte for every one
+fn apply_frames(db: &SharedDb, frames: &mut Frames, statuses: &mut Vec<u8>, verbose: bool) {
+    let metrics = &db.metrics;
+    let mut db = db.lock();
 
-/// Run server. Post is passed to the engine. Get fetches the actual state.
-pub async fn run_server(port: u16, verbose: bool) {
+    for frame in frames {
+        let status = match (frame, db.as_mut()) {
+            (Ok(transaction), Ok(db)) => {
+                if verbose {println!("{:?}", transaction)}
+                let r#type = transaction.get_type().clone();
+                let result = db.process_new_transaction(transaction);
+                metrics.record_transaction(r#type, &result);
+                match result {
+                    Ok(_) => Status::Applied,
+                    Err(e) => {
+                        if verbose {println!("E: {:?}", e)}
+                        Status::Rejected
+                    },
+                }
+            },
+            // Poisoned, nothing is applied anymore
+            (Ok(_), Err(_)) => Status::Rejected,
+            (Err(e), _) => {
+                if verbose {println!("E: {:?}", e)}
+                Status::Malformed
+            },
+        };
+        statuses.push(status as u8);
+    }
+}
+
+/// Routes of the server over the engine
+fn bind_routes(addr: impl Into<SocketAddr>, db: Arc<SharedDb>, events: broadcast::Sender<AccountEvent>, config: &Config, verbose: bool) -> (SocketAddr, impl Future<Output = ()>) {
 
-    let db = Arc::new(Mutex::new(Db::default()));
+    let cache = Arc::new(Mutex::new(IdempotencyCache::new(&config.idempotency)));
 
+    let timings = db.clone();
     let with_state = warp::any().map(move || db.clone());
+    let with_cache = warp::any().map(move || cache.clone());
+
+    // No length limit, the body is a persistent stream of frames
+    let binary = warp::header::exact("content-type", wire::CONTENT_TYPE)
+        .and(warp::body::stream())
+        .and(with_state.clone())
+        .map(move |body, db: Arc<SharedDb>| {
+            let mut response = warp::reply::Response::new(Body::wrap_stream(stream_frames(body, db, verbose)));
+            response.headers_mut().insert("content-type", warp::http::HeaderValue::from_static(wire::CONTENT_TYPE));
+            response
+        });
 
     let json = warp::header::exact("content-type", "application/json")
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::json())
+        .and(warp::header::optional::<String>("idempotency-key"))
         .and(with_state.clone())
-        .map(move |record: Transaction, db: Arc<Mutex<Db>>| {
+        .and(with_cache.clone())
+        .map(move |record: Transaction, key: Option<String>, db: Arc<SharedDb>, cache: Arc<Mutex<IdempotencyCache>>| {
             if verbose {
                 println!("{:?}", record);
             }
-            match db.lock() {
-                Ok(mut db) => {
-                    match db.process_new_transaction(record) {
-                        Ok(_) => "OK".to_string(),
-                        Err(e) => format!("Err: {}", e),
-                    }
-                },
-                Err(e) => format!("poison error: {}", e)
-            }
+            submit(&db, &cache, key, record)
         });
 
     let csv = warp::any()
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::bytes())
+        .and(warp::header::optional::<String>("idempotency-key"))
         .and(with_state.clone())
-        .map(move |record: bytes::Bytes, db: Arc<Mutex<Db>>| {
-            let mut rdr = Reader::from_reader(io::Cursor::new(record));
-
-            rdr.set_headers(StringRecord::from(vec!["type", "client", "tx", "amount"]));
-
-            if let Some(record) = rdr.deserialize::<Transaction>().next() {
+        .and(with_cache)
+        .map(move |record: bytes::Bytes, key: Option<String>, db: Arc<SharedDb>, cache: Arc<Mutex<IdempotencyCache>>| {
+            if let Some(record) = parse_csv_line(&record) {
                 match record {
                     Ok(transaction) => {
                         if verbose {
                             println!("{:?}", transaction);
                         }
-                        match db.lock() {
-                            Ok(mut db) => {
-                                match db.process_new_transaction(transaction) {
-                                    Ok(_) => "OK".to_string(),
-                                    Err(e) => format!("Err: {}", e),
-                                }
-                            },
-                            Err(e) => format!("poison error: {}", e)
-                        }
+                        submit(&db, &cache, key, transaction)
                     },
                     Err(e) => {
                         format!("Error: {:?}", e)
@@ -140,9 +683,81 @@ pub async fn run_server(port: u16, verbose: bool) {
         });
 
 
+    let flags = warp::get()
+        .and(warp::path("flags"))
+        .and(warp::path::end())
+        .and(with_state.clone())
+        .map(move |db: Arc<SharedDb>| {
+            match db.lock() {
+                Ok(db) => {
+                    format!("{}", db.monitor())
+                },
+                Err(e) => format!("poison error: {}", e)
+            }
+        });
+
+    let trial_balance = warp::get()
+        .and(warp::path("trial-balance"))
+        .and(warp::path::end())
+        .and(with_state.clone())
+        .map(move |db: Arc<SharedDb>| {
+            match db.lock() {
+                Ok(db) => {
+                    format!("{}", db.trial_balance())
+                },
+                Err(e) => format!("poison error: {}", e)
+            }
+        });
+
+    let history = warp::get()
+        .and(warp::path!("history" / u16))
+        .and(with_state.clone())
+        .map(move |client: u16, db: Arc<SharedDb>| {
+            match db.lock() {
+                Ok(db) => format_history(&db, client),
+                Err(e) => format!("poison error: {}", e)
+            }
+        });
+
+    let balance = warp::get()
+        .and(warp::path!("balance" / u16))
+        .and(warp::query::<AsOfQuery>())
+        .and(with_state.clone())
+        .map(move |client: u16, query: AsOfQuery, db: Arc<SharedDb>| {
+            let point = match (query.tx, query.at) {
+                (Some(tx), None) => AsOf::Tx { client: query.client.unwrap_or(client), tx },
+                (None, Some(at)) => AsOf::Time(at),
+                _ => return "Either tx or at is required".to_string(),
+            };
+            match db.lock() {
+                Ok(db) => format_balances_as_of(&db, client, point),
+                Err(e) => format!("poison error: {}", e)
+            }
+        });
+
+    let events = warp::get()
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(warp::query::<EventsQuery>())
+        .map(move |query: EventsQuery| {
+            warp::sse::reply(warp::sse::keep_alive().stream(sse_events(events.subscribe(), query.client)))
+        });
+
+    let metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and(with_state.clone())
+        .map(move |db: Arc<SharedDb>| {
+            let stats = match db.lock() {
+                Ok(db) => db.stats(),
+                Err(e) => return warp::reply::with_header(format!("poison error: {}", e), "content-type", metrics::CONTENT_TYPE),
+            };
+            warp::reply::with_header(db.metrics.render(&stats), "content-type", metrics::CONTENT_TYPE)
+        });
+
     let get = warp::get()
         .and(with_state)
-        .map(move |db: Arc<Mutex<Db>>| {
+        .map(move |db: Arc<SharedDb>| {
             match db.lock() {
                 Ok(db) => {
                     format!("{}", db)
@@ -152,33 +767,312 @@ pub async fn run_server(port: u16, verbose: bool) {
         });
 
     let routes = warp::post()
-                    .and(json.or(csv))
-                    .or(get);
+                    .and(binary.or(json).or(csv))
+                    .or(flags)
+                    .or(trial_balance)
+                    .or(history)
+                    .or(balance)
+                    .or(events)
+                    .or(metrics)
+                    .or(get)
+                    .with(warp::log::custom(move |info| timings.metrics.requests.observe(info.elapsed())));
 
     warp::serve(routes)
-        .run(([127, 0, 0, 1], port))
-        .await
+        .bind_ephemeral(addr)
 }
 
 
 
 
 
-/// Make actual requests
-pub async fn make_requests(url: &str, t_i: u64, n: u64, statistics: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let client = Client::new();
+/// How a single request is retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Timeout of a single attempt
+    pub timeout: Duration,
+    /// Retries after the first attempt failed or timed out
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every next one
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            retries: 3,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Configuration of a load run against the server
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// Address of the server
+    pub url: String,
+    /// Total requests to make. Ignored, if `duration` is set
+    pub requests: u64,
+    /// Concurrent workers in the closed mode
+    pub concurrent: u64,
+    /// Target rate in requests per second. Switches the run to the open mode:
+    /// requests are sent on schedule, no matter how fast the server responds
+    pub rate: Option<f64>,
+    /// Most requests in flight in the open mode, the scheduled ones over it are skipped and counted
+    pub max_in_flight: usize,
+    /// Run for the duration instead of a fixed amount of requests
+    pub duration: Option<Duration>,
+    /// Timeouts and retries of every request
+    pub retry: RetryPolicy,
+    /// Prints performance info during the run
+    pub statistics: bool,
+    /// Where to write the json report
+    pub report: Option<String>,
+}
+
+/// Sends a single json and returns the status with the body of the response
+async fn send_json(client: &Client<HttpConnector>, url: &str, json: String) -> Result<(u16, String), Box<dyn std::error::Error + Send + Sync>> {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header("content-type", "application/json")
+        .body(Body::from(json))?;
+    let resp = client.request(req).await?;
+    let status = resp.status().as_u16();
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+
+    Ok((status, String::from_utf8_lossy(&body).to_string()))
+}
+
+/// Sends a json, retrying with an exponential backoff if the request fails or times out.
+/// Returns the last outcome and the amount of retries made.
+async fn send_json_with_retries(client: &Client<HttpConnector>, url: &str, json: String, retry: RetryPolicy) -> (Outcome, u32) {
+    let mut outcome = Outcome::Failed;
+
+    for attempt in 0..=retry.retries {
+        if attempt > 0 {
+            tokio::time::sleep(retry.backoff * 2u32.saturating_pow(attempt - 1)).await;
+        }
+
+        outcome = match tokio::time::timeout(retry.timeout, send_json(client, url, json.clone())).await {
+            Ok(Ok((status, body))) => return (Outcome::Done(status, body), attempt),
+            Ok(Err(_)) => Outcome::Failed,
+            Err(_) => Outcome::TimedOut,
+        };
+    }
+
+    (outcome, retry.retries)
+}
+
+/// Stops the run on Ctrl-C. Requests in flight are finished, so the statistics stay consistent.
+fn stop_on_ctrl_c() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let flag = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Interrupted, finishing requests in flight...");
+            flag.store(true, Ordering::SeqCst);
+        }
+    });
+
+    stop
+}
+
+/// Make actual requests, one after another. Stops after `n` requests, at the `deadline` if it is set, or when stopped.
+pub async fn make_requests(client: Client<HttpConnector>, config: &LoadConfig, t_i: u64, n: u64, deadline: Option<Instant>, stop: &AtomicBool) -> Stats {
+    let mut stats = Stats::default();
 
     let mut rng = rand::thread_rng();
+    let start = Instant::now();
+    for i in 0.. {
+        match deadline {
+            Some(deadline) => if Instant::now() >= deadline { break },
+            None => if i >= n { break },
+        }
+        if stop.load(Ordering::SeqCst) {
+            break
+        }
+
+        let sent = Instant::now();
+        let (outcome, retries) = send_json_with_retries(&client, &config.url, gen_json(&mut rng), config.retry).await;
+        stats.record(sent.elapsed(), outcome, retries);
+
+        if config.statistics && i % (1024*32) == 0 && i != 0 {
+            let sec = start.elapsed().as_secs_f64() / i as f64;
+
+            println!("{} -> {:.6} sec/req at i {}", t_i, sec, i);
+        }
+    }
+
+    stats
+}
+
+/// Sends requests on schedule with the `rate` per second, without waiting for the responses (open loop).
+/// Latency is counted from the scheduled time, so a slow server can't hide the delays.
+/// At most `max_in_flight` requests are sent at once, the scheduled ones over it are skipped
+pub async fn make_scheduled_requests(client: Client<HttpConnector>, config: &LoadConfig, rate: f64, deadline: Option<Instant>, stop: &AtomicBool) -> Stats {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let in_flight = Arc::new(Semaphore::new(config.max_in_flight));
+    let mut skipped = 0;
+
+    let mut rng = rand::thread_rng();
+    let start = Instant::now();
+    for i in 0.. {
+        let scheduled = start + Duration::from_secs_f64(i as f64 / rate);
+        match deadline {
+            Some(deadline) => if scheduled >= deadline { break },
+            None => if i >= config.requests { break },
+        }
+
+        tokio::time::sleep_until(scheduled.into()).await;
+        if stop.load(Ordering::SeqCst) {
+            break
+        }
+
+        let permit = match in_flight.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                skipped += 1;
+                continue
+            },
+        };
+
+        let json = gen_json(&mut rng);
+        let client = client.clone();
+        let url = config.url.clone();
+        let retry = config.retry;
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let (outcome, retries) = send_json_with_retries(&client, &url, json, retry).await;
+            drop(permit);
+            let _ = sender.send((scheduled.elapsed(), outcome, retries));
+        });
+    }
+    drop(sender);
+
+    let mut stats = Stats { skipped, ..Stats::default() };
+    while let Some((latency, outcome, retries)) = receiver.recv().await {
+        stats.record(latency, outcome, retries);
+    }
+
+    stats
+}
+
+/// Generate jsons and send them to a server, either by concurrent workers or with a fixed rate.
+/// Prints the report and writes it as json, if asked. Ctrl-C stops the run and still reports the partial statistics.
+pub async fn run_server_fuzz(config: &LoadConfig) -> Result<Report, Box<dyn std::error::Error + Send + Sync>> {
+
+    let client = Client::new();
+    let stop = stop_on_ctrl_c();
+    let start = Instant::now();
+    let deadline = config.duration.map(|d| start + d);
+
+    let stats = match config.rate {
+        Some(rate) => {
+            make_scheduled_requests(client, config, rate, deadline, &stop).await
+        },
+        None => {
+            // The remainder goes to the first workers, one request each
+            let (share, remainder) = (config.requests / config.concurrent, config.requests % config.concurrent);
+            let children = (0..config.concurrent)
+                .map(|t_i| make_requests(client.clone(), config, t_i, share + u64::from(t_i < remainder), deadline, &stop));
+
+            let mut stats = Stats::default();
+            for worker_stats in join_all(children).await {
+                stats.merge(worker_stats);
+            }
+            stats
+        },
+    };
+
+    let mode = if config.rate.is_some() { "open" } else { "closed" };
+    let mut report = Report::new(&config.url, mode, config.concurrent, config.rate, start.elapsed(), stats);
+    report.interrupted = stop.load(Ordering::SeqCst);
+
+    print!("{}", report);
+
+    if let Some(location) = &config.report {
+        serde_json::to_writer_pretty(File::create(location)?, &report)?;
+    }
+
+    Ok(report)
+}
+
+/// Reads the csv the same way the engine does (with trimming) and returns the records as single csv lines,
+/// without the header, ready to be sent to the server. The columns are put in the order the server expects them
+/// (see `parse_csv_line`), whatever the order in the header is; the missing ones are empty, the unknown ones are dropped.
+/// Records the engine would fail to read are skipped. Returns the client of every line with it
+pub fn read_csv_lines(location: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(location)?;
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b',')
+        .trim(csv::Trim::All)
+        .from_reader(file);
+
+    // Position of every server column in the file
+    let headers = rdr.headers()?.clone();
+    let positions: Vec<Option<usize>> = CSV_COLUMNS.iter()
+        .map(|column| headers.iter().position(|header| header == *column))
+        .collect();
+
+    let mut lines = vec![];
+    for record in rdr.records().flatten() {
+        let mut fields: Vec<&str> = positions.iter()
+            .map(|position| position.and_then(|i| record.get(i)).unwrap_or(""))
+            .collect();
+        // The optional columns are picked by the amount of fields
+        while fields.len() > 4 && fields.last() == Some(&"") {
+            fields.pop();
+        }
+
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(vec![]);
+        wtr.write_record(&fields)?;
+        let line = String::from_utf8(wtr.into_inner()?)?;
+
+        lines.push((fields[1].to_string(), line.trim_end().to_string()));
+    }
+
+    Ok(lines)
+}
+
+/// Splits the lines into `shards` groups by client, keeping the order of the lines inside of every client
+pub fn shard_by_client(lines: Vec<(String, String)>, shards: usize) -> Vec<Vec<String>> {
+    let mut sharded = vec![vec![]; shards.max(1)];
+    let n = sharded.len();
+
+    for (client, line) in lines {
+        let shard = match client.parse::<u16>() {
+            Ok(id) => id as usize % n,
+            Err(_) => 0,
+        };
+        sharded[shard].push(line);
+    }
+
+    sharded
+}
+
+/// Sends csv lines one by one, in order. Fails at the first response, which is not 2xx
+pub async fn send_lines(url: &str, t_i: usize, lines: Vec<String>, statistics: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+
     let start: DateTime<Local> = Local::now();
-    for i in 0..n {
+    for (i, line) in lines.into_iter().enumerate() {
         let req = Request::builder()
             .method(Method::POST)
             .uri(url)
-            .header("content-type", "application/json")
-            .body(Body::from(gen_json(&mut rng))).unwrap();
-        let _ = client.request(req).await.expect("Couldn't make a request, please check that the server is running");
+            .header("content-type", "text/csv")
+            .body(Body::from(line))?;
+        let resp = client.request(req).await?;
+        if !resp.status().is_success() {
+            return Err(format!("Line {} of worker {}: status {}", i, t_i, resp.status()).into())
+        }
 
-        if statistics && i % 1024*32 == 0 && i != 0 {
+        if statistics && i % (1024*32) == 0 && i != 0 {
             let elapsed = Local::now()-start;
             let microsec = elapsed.num_microseconds().unwrap() / i as i64;
             let sec = microsec as f64 / 1000000.0;
@@ -190,30 +1084,74 @@ pub async fn make_requests(url: &str, t_i: u64, n: u64, statistics: bool) -> Res
     Ok(())
 }
 
-/// Generate jsons and send them to a server somewhat concurrently
-pub async fn run_server_fuzz(url: &str, n: u64, concurrent: u64, statistics: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Fetches the state of the server
+pub async fn fetch_state(url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+
+    let resp = client.get(url.parse()?).await?;
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+
+    Ok(String::from_utf8(body.to_vec())?)
+}
+
+/// Compares two states rendered by `Db`'s `Display`. Accounts order doesn't matter.
+/// Returns the lines, which are present only in one of the states, prefixed by `-` (expected) or `+` (actual)
+pub fn diff_states(expected: &str, actual: &str) -> Vec<String> {
+    let to_set = |state: &str| state.lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect::<BTreeSet<String>>();
+
+    let expected = to_set(expected);
+    let actual = to_set(actual);
+
+    expected.difference(&actual).map(|line| format!("- {}", line))
+        .chain(actual.difference(&expected).map(|line| format!("+ {}", line)))
+        .collect()
+}
+
+/// Replays a csv file against the server, either in order, or sharded by client across concurrent workers.
+/// Afterwards compares the state of the server with the state `db_from_file` would produce with the same config.
+pub async fn run_server_replay(url: &str, location: &str, config: &Config, concurrent: u64, in_order: bool, statistics: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+
+    let lines = read_csv_lines(location)?;
+    let n = lines.len();
+
+    let shards = if in_order {
+        vec![lines.into_iter().map(|(_, line)| line).collect()]
+    } else {
+        shard_by_client(lines, concurrent as usize)
+    };
+    let workers = shards.len();
 
     let start: DateTime<Local> = Local::now();
 
-    let mut children = vec![];
+    let children = shards.into_iter()
+        .enumerate()
+        .map(|(t_i, shard)| send_lines(url, t_i, shard, statistics));
 
-    for t_i in 0..concurrent {
-        children.push(make_requests(url, t_i, n/concurrent, statistics));
+    for result in join_all(children).await {
+        result?;
     }
 
-    join_all(children).await;
-
     let elapsed = Local::now() - start;
+    let sec_total = elapsed.num_microseconds().unwrap() as f64 / 1000000.0;
 
-    let microsec_total = elapsed.num_microseconds().unwrap();
-    let sec_total = microsec_total as f64 / 1000000.0;
-
-    let microsec_per_request = microsec_total / n as i64;
-    let sec_per_request = microsec_per_request as f64 / 1000000.0;
+    println!("Replayed {} lines in {:.6} sec total. Workers || {}", n, sec_total, workers);
 
-    println!("Done {} requests in {:.6} sec total, with {:.7} sec/req. Concurrency || {}", n, sec_total, sec_per_request, concurrent);
+    let actual = fetch_state(url).await?;
+    let expected = format!("{}", db_from_file(location, config, false)?);
 
-    Ok(())
+    let diff = diff_states(&expected, &actual);
+    if diff.is_empty() {
+        println!("Server state matches the offline result");
+        Ok(())
+    } else {
+        for line in &diff {
+            println!("{}", line);
+        }
+        Err(format!("Server state differs from the offline result in {} lines", diff.len()).into())
+    }
 }
 
 /// Generate csv lines and print them into stdin
@@ -225,4 +1163,85 @@ pub fn gen_lines(n: u64) {
     for _ in 0..n {
         println!("{}", gen_line(&mut rng))
     }
-}
\ No newline at end of file
+}
+/// Generate binary frames and write them into stdout
+pub fn gen_frames(n: u64) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..n {
+        io::Write::write_all(&mut out, &gen_frame(&mut rng))?;
+    }
+    io::Write::flush(&mut out)
+}
+
+/// Where the fuzzer sends binary frames
+#[derive(Debug, Clone)]
+pub enum WireTarget {
+    /// Raw TCP listener, as `host:port`
+    Tcp(String),
+    /// Streamed http body, address of the server
+    Http(String),
+}
+
+/// Sends the frames over a single connection, reading the statuses meanwhile
+async fn send_frames_tcp(addr: &str, frames: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let (mut reader, mut writer) = TcpStream::connect(addr).await?.into_split();
+
+    let writing = tokio::spawn(async move {
+        writer.write_all(&frames).await?;
+        writer.shutdown().await
+    });
+
+    let mut statuses = Vec::new();
+    reader.read_to_end(&mut statuses).await?;
+    writing.await??;
+
+    Ok(statuses)
+}
+
+/// Sends the frames as a single streamed body, in chunks
+async fn send_frames_http(url: &str, frames: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    const CHUNK: usize = 64 * 1024;
+
+    let frames = bytes::Bytes::from(frames);
+    let chunks = (0..frames.len()).step_by(CHUNK)
+        .map(|i| Ok::<_, io::Error>(frames.slice(i..frames.len().min(i + CHUNK))))
+        .collect::<Vec<_>>();
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header("content-type", wire::CONTENT_TYPE)
+        .body(Body::wrap_stream(futures::stream::iter(chunks)))?;
+    let resp = Client::new().request(req).await?;
+
+    Ok(hyper::body::to_bytes(resp.into_body()).await?.to_vec())
+}
+
+/// Generates `n` binary frames, sends them to the server in a single stream and prints the rate with the statuses.
+/// Frames are generated before the timing starts
+pub async fn run_wire_fuzz(target: &WireTarget, n: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let frames = {
+        let mut rng = rand::thread_rng();
+        (0..n).flat_map(|_| gen_frame(&mut rng)).collect::<Vec<u8>>()
+    };
+    let size = frames.len();
+
+    let start = Instant::now();
+    let statuses = match target {
+        WireTarget::Tcp(addr) => send_frames_tcp(addr, frames).await?,
+        WireTarget::Http(url) => send_frames_http(url, frames).await?,
+    };
+    let sec_total = start.elapsed().as_secs_f64();
+
+    let count = |status: Status| statuses.iter().filter(|s| **s == status as u8).count();
+    println!("Sent {} frames ({} bytes) in {:.6} sec total, {:.0} frames/sec", n, size, sec_total, n as f64 / sec_total);
+    println!("Applied: {}, rejected: {}, malformed: {}", count(Status::Applied), count(Status::Rejected), count(Status::Malformed));
+
+    if statuses.len() as u64 != n {
+        return Err(format!("Got {} statuses for {} frames", statuses.len(), n).into())
+    }
+    Ok(())
+}
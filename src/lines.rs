@@ -0,0 +1,171 @@
+use serde::Deserialize;
+
+use std::fmt;
+
+
+/// Limits of the line-protocol listener
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct LineLimits {
+    /// Connections served at once, the ones above are refused
+    pub max_connections: usize,
+    /// Longest line in bytes, without the newline. Longer ones are rejected and skipped
+    pub max_line: usize,
+}
+
+impl Default for LineLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_line: 1024 * 32,
+        }
+    }
+}
+
+/// Line is longer than the limit - contains the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineTooLong(pub usize);
+
+impl fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line is longer than {} bytes", self.0)
+    }
+}
+
+/// Newline-delimited lines of a byte stream, which comes in arbitrary chunks. `\r\n` is accepted as well.
+/// A line longer than the limit is reported once, as soon as it's known, and the rest of it is skipped
+#[derive(Debug)]
+pub struct Lines {
+    buf: Vec<u8>,
+    /// Start of the first line, which isn't read yet
+    start: usize,
+    max: usize,
+    /// Inside of a too long line, which is already reported
+    skipping: bool,
+}
+
+impl Lines {
+
+    /// Constructor
+    pub fn new(max: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            start: 0,
+            max,
+            skipping: false,
+        }
+    }
+
+    /// Adds the next chunk of the stream
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Next complete line, `None` if there is none yet
+    pub fn next_line(&mut self) -> Option<Result<&[u8], LineTooLong>> {
+        loop {
+            let rest = &self.buf[self.start..];
+            match rest.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    let start = self.start;
+                    self.start += end + 1;
+                    if self.skipping {
+                        self.skipping = false;
+                        continue
+                    }
+                    return Some(self.line(start, start + end))
+                },
+                None => {
+                    let long = rest.len() > self.max;
+                    if self.skipping || long {
+                        self.start = self.buf.len();
+                    }
+                    if long && !self.skipping {
+                        self.skipping = true;
+                        return Some(Err(LineTooLong(self.max)))
+                    }
+                    return None
+                },
+            }
+        }
+    }
+
+    /// Last line of an ended stream, if it isn't terminated by a newline
+    pub fn finish(&mut self) -> Option<Result<&[u8], LineTooLong>> {
+        if self.skipping || self.start == self.buf.len() {
+            return None
+        }
+
+        let (start, end) = (self.start, self.buf.len());
+        self.start = end;
+        Some(self.line(start, end))
+    }
+
+    fn line(&self, start: usize, end: usize) -> Result<&[u8], LineTooLong> {
+        let line = &self.buf[start..end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.len() > self.max {
+            Err(LineTooLong(self.max))
+        } else {
+            Ok(line)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every line of the stream pushed in chunks of `size`
+    fn read(stream: &[u8], size: usize, max: usize) -> Vec<Result<String, LineTooLong>> {
+        let mut lines = Lines::new(max);
+        let mut result = Vec::new();
+        let to_string = |line: Result<&[u8], LineTooLong>| line.map(|l| String::from_utf8_lossy(l).to_string());
+
+        for chunk in stream.chunks(size) {
+            lines.push(chunk);
+            while let Some(line) = lines.next_line() {
+                result.push(to_string(line));
+            }
+        }
+        if let Some(line) = lines.finish() {
+            result.push(to_string(line));
+        }
+
+        result
+    }
+
+    #[test]
+    fn splits_lines_in_any_chunks() {
+        let stream = b"deposit,1,1,1.0\r\n\n{\"type\": \"dispute\"}\nlast";
+        let expected = vec![
+            Ok("deposit,1,1,1.0".to_string()),
+            Ok("".to_string()),
+            Ok("{\"type\": \"dispute\"}".to_string()),
+            Ok("last".to_string()),
+        ];
+
+        for size in [1, 2, 5, stream.len()] {
+            assert_eq!(read(stream, size, 64), expected);
+        }
+    }
+
+    #[test]
+    fn too_long_lines_are_reported_once() {
+        let stream = b"short\n0123456789abcdef\nok\n0123456789abcdef";
+
+        for size in [1, 3, stream.len()] {
+            assert_eq!(read(stream, size, 8), vec![
+                Ok("short".to_string()),
+                Err(LineTooLong(8)),
+                Ok("ok".to_string()),
+                Err(LineTooLong(8)),
+            ]);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use rustc_hash::FxHashMap;
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LockResult, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::db::{DBError, Db, DbStats};
+use crate::db::transaction::TransactionType;
+
+
+/// Content type of the metrics, the Prometheus text format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds of the request latency buckets, in seconds
+const REQUEST_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Upper bounds of the lock wait buckets, in seconds
+const LOCK_BUCKETS: &[f64] = &[0.000001, 0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0];
+
+/// Histogram of durations with fixed buckets, lock-free
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative. The last one is above every bound
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_ns: AtomicU64,
+}
+
+impl Histogram {
+
+    /// Constructor, the bounds are in seconds and ascending
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds the observation
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = self.bounds.iter().position(|bound| secs <= *bound).unwrap_or(self.bounds.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Observations so far
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += self.buckets[self.bounds.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_ns.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+    }
+}
+
+/// Operational metrics of the server
+#[derive(Debug)]
+pub struct Metrics {
+    /// Processed transactions by the type and the outcome: `ok` or the kind of the error
+    transactions: Mutex<FxHashMap<(TransactionType, &'static str), u64>>,
+    /// Latency of the http requests
+    pub requests: Histogram,
+    /// Wait for the lock of the engine
+    pub lock_wait: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            transactions: Mutex::default(),
+            requests: Histogram::new(REQUEST_BUCKETS),
+            lock_wait: Histogram::new(LOCK_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+
+    /// Counts the processed transaction
+    pub fn record_transaction(&self, r#type: TransactionType, result: &Result<(), DBError>) {
+        let outcome = match result {
+            Ok(_) => "ok",
+            Err(e) => e.kind(),
+        };
+        if let Ok(mut transactions) = self.transactions.lock() {
+            *transactions.entry((r#type, outcome)).or_default() += 1;
+        }
+    }
+
+    /// Metrics in the Prometheus text format, with the sizes of the engine
+    pub fn render(&self, stats: &DbStats) -> String {
+        let mut out = String::new();
+
+        let mut transactions: Vec<(String, &str, u64)> = match self.transactions.lock() {
+            Ok(transactions) => transactions.iter().map(|((t, outcome), n)| (t.to_string(), *outcome, *n)).collect(),
+            Err(_) => Vec::new(),
+        };
+        transactions.sort_unstable();
+
+        out.push_str("# HELP case_transactions_total Transactions processed by the server, by type and outcome\n");
+        out.push_str("# TYPE case_transactions_total counter\n");
+        for (r#type, outcome, n) in transactions {
+            let _ = writeln!(out, "case_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}", r#type, outcome, n);
+        }
+
+        self.requests.render(&mut out, "case_request_duration_seconds", "Latency of the http requests");
+        self.lock_wait.render(&mut out, "case_db_lock_wait_seconds", "Wait for the lock of the engine");
+
+        let gauges = [
+            ("case_accounts", "Accounts in the engine", stats.accounts),
+            ("case_stored_transactions", "Transactions kept in memory to be disputed", stats.transactions),
+            ("case_locked_accounts", "Locked accounts", stats.locked),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+        }
+
+        out.push_str("# HELP case_held_funds Held funds of all the accounts, by asset\n");
+        out.push_str("# TYPE case_held_funds gauge\n");
+        for (asset, held) in &stats.held {
+            let _ = writeln!(out, "case_held_funds{{asset=\"{}\"}} {}", escape(asset), held);
+        }
+
+        out
+    }
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Engine shared by the server, every lock of it is timed into the metrics
+pub struct SharedDb {
+    db: Mutex<Db>,
+    pub metrics: Metrics,
+}
+
+impl SharedDb {
+
+    /// Constructor
+    pub fn new(db: Db) -> Self {
+        Self {
+            db: Mutex::new(db),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Locks the engine, recording the wait
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, Db>> {
+        let start = Instant::now();
+        let db = self.db.lock();
+        self.metrics.lock_wait.observe(start.elapsed());
+        db
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::account::error::AccountError;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[0.001, 0.01]);
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_secs(1));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "h", "help");
+
+        assert!(out.contains("h_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("h_bucket{le=\"0.01\"} 2\n"));
+        assert!(out.contains("h_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("h_sum 1.0055\n"));
+        assert!(out.contains("h_count 3\n"));
+    }
+
+    #[test]
+    fn renders_the_counters_and_the_gauges() {
+        let metrics = Metrics::default();
+        metrics.record_transaction(TransactionType::Deposit, &Ok(()));
+        metrics.record_transaction(TransactionType::Deposit, &Ok(()));
+        metrics.record_transaction(TransactionType::Withdrawal, &Err(AccountError::TooMuch(dec!(1)).into()));
+        metrics.record_transaction(TransactionType::Transfer, &Err(DBError::TransferToItself));
+
+        let mut stats = DbStats { accounts: 2, transactions: 3, locked: 1, ..DbStats::default() };
+        stats.held.insert("".to_string(), dec!(1.5));
+        stats.held.insert("E\"UR".to_string(), dec!(2));
+
+        let out = metrics.render(&stats);
+        for line in [
+            "case_transactions_total{type=\"deposit\",outcome=\"ok\"} 2",
+            "case_transactions_total{type=\"withdrawal\",outcome=\"too_much\"} 1",
+            "case_transactions_total{type=\"transfer\",outcome=\"transfer_to_itself\"} 1",
+            "case_accounts 2",
+            "case_stored_transactions 3",
+            "case_locked_accounts 1",
+            "case_held_funds{asset=\"\"} 1.5",
+            "case_held_funds{asset=\"E\\\"UR\"} 2",
+        ] {
+            assert!(out.lines().any(|l| l == line), "{} is missing in\n{}", line, out);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use csv::{ByteRecord, Reader, StringRecord};
+use rust_decimal::Decimal;
+
+use std::convert::TryFrom;
+use std::io;
+use std::str;
+
+use crate::db::transaction::{Transaction, TransactionType};
+use crate::Monetary;
+
+
+/// Most digits of an amount parsed by hand. Serde reads the fractional amounts through f64,
+/// and up to 15 significant digits f64 gives back exactly the same number
+const MAX_AMOUNT_DIGITS: usize = 15;
+
+/// Positions of the known columns in the header
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    r#type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    asset: Option<usize>,
+    counterparty: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl Columns {
+
+    /// Finds the columns. `None` if a required one is missing or any is duplicated, serde rejects every record then
+    fn new(headers: &StringRecord) -> Option<Self> {
+        let find = |name: &str| -> Option<Option<usize>> {
+            let mut found = headers.iter().enumerate().filter(|(_, h)| *h == name).map(|(i, _)| i);
+            let first = found.next();
+            match found.next() {
+                Some(_) => None,
+                None => Some(first),
+            }
+        };
+
+        Some(Self {
+            r#type: find("type")??,
+            client: find("client")??,
+            tx: find("tx")??,
+            amount: find("amount")?,
+            asset: find("asset")?,
+            counterparty: find("counterparty")?,
+            timestamp: find("timestamp")?,
+        })
+    }
+}
+
+/// Transactions of a csv reader. Records are read as bytes and parsed by hand, when they are plain:
+/// known type, decimal integers and amounts of at most 15 digits. Anything else goes through serde,
+/// so the accepted and rejected records are the same as of `Reader::deserialize`
+pub struct Transactions<R> {
+    rdr: Reader<R>,
+    /// Headers for serde, `None` if they are not valid utf-8 (serde goes by the positions then)
+    headers: Option<StringRecord>,
+    /// `None` if every record goes through serde
+    columns: Option<Columns>,
+    record: ByteRecord,
+    /// Failure to read the headers, returned first
+    failed: Option<csv::Error>,
+}
+
+impl<R: io::Read> Transactions<R> {
+
+    /// Constructor. The reader is expected to read the headers
+    pub fn new(mut rdr: Reader<R>) -> Self {
+        let (headers, failed) = match rdr.headers() {
+            Ok(headers) => (Some(headers.clone()), None),
+            Err(e) if matches!(e.kind(), csv::ErrorKind::Io(_)) => (None, Some(e)),
+            Err(_) => (None, None),
+        };
+        let columns = headers.as_ref().and_then(Columns::new);
+
+        Self {
+            rdr,
+            headers,
+            columns,
+            record: ByteRecord::new(),
+            failed,
+        }
+    }
+
+    /// Parses the record through serde, exactly as `Reader::deserialize` does
+    fn deserialize(&self) -> Result<Transaction, csv::Error> {
+        let record = match StringRecord::from_byte_record(self.record.clone()) {
+            Ok(record) => record,
+            Err(_) => return Err(utf8_error(&self.record)),
+        };
+
+        record.deserialize(self.headers.as_ref())
+    }
+}
+
+impl<R: io::Read> Iterator for Transactions<R> {
+    type Item = Result<Transaction, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.failed.take() {
+            return Some(Err(e))
+        }
+
+        match self.rdr.read_byte_record(&mut self.record) {
+            Ok(true) => {},
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let fast = self.columns.and_then(|columns| parse_record(&self.record, &columns));
+
+        Some(fast.map_or_else(|| self.deserialize(), Ok))
+    }
+}
+
+/// Error of a record with invalid utf-8, the one a csv reader returns for it: the record is read back as a string one.
+/// Not an i/o error, so it only skips the record
+fn utf8_error(record: &ByteRecord) -> csv::Error {
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    let data = wtr.write_byte_record(record).ok().and_then(|_| wtr.into_inner().ok()).unwrap_or_default();
+
+    let mut rdr = csv::ReaderBuilder::new().has_headers(false).from_reader(data.as_slice());
+    match rdr.read_record(&mut StringRecord::new()) {
+        Err(e) => e,
+        Ok(_) => unreachable!("the record has invalid utf-8"),
+    }
+}
+
+/// Parses a plain record by hand. `None` if it's not plain, serde should decide then
+fn parse_record(record: &ByteRecord, columns: &Columns) -> Option<Transaction> {
+    // Serde reads records as strings, so a single invalid byte rejects the record
+    str::from_utf8(record.as_slice()).ok()?;
+
+    let r#type = match record.get(columns.r#type)? {
+        b"deposit" => TransactionType::Deposit,
+        b"withdrawal" => TransactionType::Withdrawal,
+        b"dispute" => TransactionType::Dispute,
+        b"resolve" => TransactionType::Resolve,
+        b"chargeback" => TransactionType::Chargeback,
+        b"transfer" => TransactionType::Transfer,
+        _ => return None,
+    };
+    let client = parse_uint(record.get(columns.client)?)?;
+    let tx = parse_uint(record.get(columns.tx)?)?;
+    let amount = match optional(record, columns.amount) {
+        Some(field) => Some(parse_amount(field)?),
+        None => None,
+    };
+
+    let mut t = Transaction::new(r#type, u16::try_from(client).ok()?, u32::try_from(tx).ok()?, amount, false);
+
+    if let Some(asset) = optional(record, columns.asset) {
+        t = t.with_asset(str::from_utf8(asset).ok()?);
+    }
+    if let Some(counterparty) = optional(record, columns.counterparty) {
+        t = t.with_counterparty(u16::try_from(parse_uint(counterparty)?).ok()?);
+    }
+    if let Some(timestamp) = optional(record, columns.timestamp) {
+        t = t.with_timestamp(parse_uint(timestamp)?);
+    }
+
+    Some(t)
+}
+
+/// Field of an optional column, `None` if there is no column or the field is empty
+fn optional(record: &ByteRecord, column: Option<usize>) -> Option<&[u8]> {
+    column.and_then(|i| record.get(i)).filter(|field| !field.is_empty())
+}
+
+/// Decimal digits only. `None` on anything else or an overflow
+fn parse_uint(field: &[u8]) -> Option<u64> {
+    if field.is_empty() {
+        return None
+    }
+
+    field.iter().try_fold(0u64, |n, &b| {
+        if b.is_ascii_digit() {
+            n.checked_mul(10)?.checked_add((b - b'0') as u64)
+        } else {
+            None
+        }
+    })
+}
+
+/// Amount as `[+-]digits[.digits]`, at most 15 digits. `None` on anything else.
+/// The result is normalized, as serde gives it
+pub fn parse_amount(field: &[u8]) -> Option<Monetary> {
+    let (negative, digits) = match field.first()? {
+        b'-' => (true, &field[1..]),
+        b'+' => (false, &field[1..]),
+        _ => (false, field),
+    };
+
+    let mut mantissa: i64 = 0;
+    let mut count = 0;
+    let mut scale = None;
+
+    for (i, &b) in digits.iter().enumerate() {
+        match b {
+            b'0'..=b'9' => {
+                mantissa = mantissa * 10 + (b - b'0') as i64;
+                count += 1;
+                if count > MAX_AMOUNT_DIGITS {
+                    return None
+                }
+            },
+            b'.' if scale.is_none() => scale = Some(i),
+            _ => return None,
+        }
+    }
+
+    if count == 0 {
+        return None
+    }
+
+    let scale = scale.map_or(0, |dot| digits.len() - dot - 1) as u32;
+    let amount = Decimal::new(if negative { -mantissa } else { mantissa }, scale);
+
+    Some(amount.normalize())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fuzzing::gen_line;
+
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use std::fs;
+    use std::path::Path;
+
+    fn reader(data: &[u8]) -> Reader<&[u8]> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data)
+    }
+
+    fn fields(t: &Transaction) -> (TransactionType, u16, u32, Option<Monetary>, String, Option<u16>, Option<u64>) {
+        (t.get_type().clone(), t.client(), t.tx(), t.amount(), t.asset().to_string(), t.counterparty(), t.timestamp())
+    }
+
+    /// Both paths accept and reject the same records, and the accepted ones are the same
+    fn assert_same(data: &[u8]) {
+        let expected: Vec<_> = reader(data).deserialize::<Transaction>()
+            .map(|r| r.map(|t| fields(&t)).ok())
+            .collect();
+        let actual: Vec<_> = Transactions::new(reader(data))
+            .map(|r| r.map(|t| fields(&t)).ok())
+            .collect();
+
+        assert_eq!(expected, actual, "{}", String::from_utf8_lossy(data));
+    }
+
+    #[test]
+    fn amounts() {
+        assert_eq!(parse_amount(b"1.50"), Some(Decimal::new(15, 1)));
+        assert_eq!(parse_amount(b"-.5"), Some(Decimal::new(-5, 1)));
+        assert_eq!(parse_amount(b"+5."), Some(Decimal::new(5, 0)));
+        assert_eq!(parse_amount(b"123456789012345"), Some(Decimal::new(123456789012345, 0)));
+        assert_eq!(parse_amount(b"1234567890123456"), None);
+        for field in [&b"."[..], b"", b"-", b"1.2.3", b"1e5", b"0x10", b" 1", b"true"] {
+            assert_eq!(parse_amount(field), None);
+        }
+    }
+
+    #[test]
+    fn same_decisions_as_serde() {
+        let cases: &[&[u8]] = &[
+            b"type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal, 2 ,3, 0.5 \ndispute,1,1,\nDeposit,1,2,1\n",
+            b"type,client,tx,amount\ndeposit,0x10,1,1\ndeposit,+1,2,1\ndeposit,65536,3,1\ndeposit,1,4294967296,1\n",
+            b"type,client,tx,amount\ndeposit,1,1,1e5\ndeposit,1,2,true\ndeposit,1,3,.5\ndeposit,1,4,5.\ndeposit,1,5,.\n",
+            b"type,client,tx,amount\ndeposit,1,1,79228162514264337593543950335\ndeposit,1,2,0.0000000000000000000000000001\n",
+            b"type,client,tx,amount\ndeposit,1,1,18446744073709551616\ndeposit,1,2,-9223372036854775809\ndeposit,1,3,inf\n",
+            b"type,client,tx,amount\ndeposit,1,1\ndeposit,1,2,1,1\ndeposit,1,3,\xff\n",
+            b"type,client,tx,amount,asset,counterparty,timestamp\ntransfer,1,1,1,EUR,2,1700000000000\ndeposit,1,2,1,,,\ntransfer,1,3,1,,0x2,\n",
+            b"client,tx,type,extra,amount\n1,1,deposit,\xff,1\n1,2,deposit,x,1\n",
+            b"type,client,tx,amount,amount\ndeposit,1,1,1,2\n",
+            b"type,tx,amount\ndeposit,1,1\n",
+            b"type,client,tx,\xffamount\ndeposit,1,1,1\n",
+            b"",
+        ];
+
+        for data in cases {
+            assert_same(data);
+        }
+    }
+
+    #[test]
+    fn same_decisions_on_the_fuzzer_input() {
+        let mut rng = StdRng::seed_from_u64(44);
+        let mut data = "type,client,tx,amount\n".to_string();
+        for _ in 0..20_000 {
+            data.push_str(&gen_line(&mut rng));
+            data.push('\n');
+        }
+        assert_same(data.as_bytes());
+
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/csv_stream");
+        for entry in fs::read_dir(corpus).unwrap() {
+            assert_same(&fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::db::transaction::Transaction;
+
+
+/// Transaction waiting in the buffer. Ordered by the timestamp, then by the arrival
+struct Pending {
+    timestamp: u64,
+    seq: u64,
+    transaction: Transaction,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
+/// Reorders slightly out-of-order input by the event time.
+/// A transaction is held until the input moves `window` milliseconds past its timestamp, then released in the timestamp order.
+/// Transactions without a timestamp take the latest one seen. The ones arriving later than the window can't be reordered,
+/// so they are released as soon as possible, in the arrival order
+pub struct Reorder {
+    window: u64,
+    buffer: BinaryHeap<Reverse<Pending>>,
+    seq: u64,
+    /// Latest timestamp seen
+    latest: u64,
+    /// Timestamp of the last released transaction
+    released: u64,
+    /// Amount of transactions arrived too late
+    late: u64,
+}
+
+impl Reorder {
+
+    /// Constructor, the window is in milliseconds
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            buffer: BinaryHeap::new(),
+            seq: 0,
+            latest: 0,
+            released: 0,
+            late: 0,
+        }
+    }
+
+    /// Amount of transactions arrived later than the window allows
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Adds a transaction to the buffer. Returns the transactions ready to be applied, in order
+    pub fn push(&mut self, transaction: Transaction) -> Vec<Transaction> {
+        let mut timestamp = transaction.timestamp().unwrap_or(self.latest);
+        if timestamp < self.released {
+            self.late += 1;
+            timestamp = self.released;
+        }
+
+        self.latest = self.latest.max(timestamp);
+        self.seq += 1;
+        self.buffer.push(Reverse(Pending { timestamp, seq: self.seq, transaction }));
+
+        let mut ready = Vec::new();
+        while let Some(Reverse(pending)) = self.buffer.peek() {
+            if pending.timestamp.saturating_add(self.window) > self.latest {
+                break
+            }
+            if let Some(Reverse(pending)) = self.buffer.pop() {
+                self.released = pending.timestamp;
+                ready.push(pending.transaction);
+            }
+        }
+
+        ready
+    }
+
+    /// Releases everything left in the buffer, at the end of the input
+    pub fn flush(&mut self) -> Vec<Transaction> {
+        let mut ready = Vec::with_capacity(self.buffer.len());
+        while let Some(Reverse(pending)) = self.buffer.pop() {
+            self.released = pending.timestamp;
+            ready.push(pending.transaction);
+        }
+
+        ready
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::transaction::TransactionType;
+
+    fn at(tx: u32, timestamp: Option<u64>) -> Transaction {
+        let t = Transaction::new(TransactionType::Deposit, 1, tx, None, false);
+        match timestamp {
+            Some(timestamp) => t.with_timestamp(timestamp),
+            None => t,
+        }
+    }
+
+    fn txs(transactions: Vec<Transaction>) -> Vec<u32> {
+        transactions.iter().map(|t| t.tx()).collect()
+    }
+
+    #[test]
+    fn reorders_within_the_window() {
+        let mut reorder = Reorder::new(100);
+
+        assert!(reorder.push(at(1, Some(1000))).is_empty());
+        assert!(reorder.push(at(2, Some(1050))).is_empty());
+        // Earlier than the previous ones, but within the window
+        assert!(reorder.push(at(3, Some(990))).is_empty());
+        assert_eq!(txs(reorder.push(at(4, Some(1095)))), vec![3]);
+        assert_eq!(txs(reorder.push(at(5, Some(1200)))), vec![1, 2, 4]);
+
+        // Too late: 1095 was already released, so it goes right away
+        assert_eq!(txs(reorder.push(at(6, Some(1000)))), vec![6]);
+        assert_eq!(reorder.late(), 1);
+        // Without a timestamp: the latest one
+        assert!(reorder.push(at(7, None)).is_empty());
+
+        assert_eq!(txs(reorder.flush()), vec![5, 7]);
+    }
+
+    #[test]
+    fn zero_window_keeps_the_order() {
+        let mut reorder = Reorder::new(0);
+
+        assert_eq!(txs(reorder.push(at(1, Some(5)))), vec![1]);
+        assert_eq!(txs(reorder.push(at(2, Some(3)))), vec![2]);
+        assert_eq!(txs(reorder.push(at(3, None))), vec![3]);
+        assert_eq!(reorder.late(), 1);
+    }
+}
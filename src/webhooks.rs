@@ -0,0 +1,564 @@
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use hmac::{Hmac, Mac};
+use hyper::{Body, Client, Method, Request};
+use hyper::client::HttpConnector;
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Instant;
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::events::AccountEvent;
+use crate::db::transaction::{now_ms, TransactionType};
+
+
+/// Header with the milliseconds since the Unix epoch the webhook was signed at
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+/// Header with `sha256=<hex>`: HMAC-SHA256 of `<timestamp>.<body>` with the secret
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Kinds of the webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookKind {
+    /// Account got locked, by a chargeback or a fraud rule
+    AccountLocked,
+    Chargeback,
+    DisputeOpened,
+}
+
+/// Outbound webhooks of the server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Webhooks {
+    /// Every webhook is sent to each of them. No webhooks if empty
+    pub urls: Vec<String>,
+    /// Kinds to send
+    pub events: Vec<WebhookKind>,
+    /// Signs the webhooks, if set
+    pub secret: Option<String>,
+    /// Timeout of a single attempt
+    pub timeout_ms: u64,
+    /// Retries after the first attempt failed
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every next one
+    pub backoff_ms: u64,
+    /// Json lines of the webhooks, which couldn't be delivered after all the retries or didn't fit into the queue.
+    /// They are dropped, if not set
+    pub dead_letter: Option<PathBuf>,
+    /// Webhooks waiting for the delivery, and attempts in flight or waiting for a retry, at most each
+    pub queue: usize,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            events: vec![WebhookKind::AccountLocked, WebhookKind::Chargeback, WebhookKind::DisputeOpened],
+            secret: None,
+            timeout_ms: 5000,
+            retries: 5,
+            backoff_ms: 500,
+            dead_letter: None,
+            queue: 10_000,
+        }
+    }
+}
+
+impl Webhooks {
+
+    /// No urls to send to
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+}
+
+/// Body of a webhook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    /// Unique id, a receiver could use it to skip the retried ones it already got
+    pub id: String,
+    pub event: WebhookKind,
+    pub client: u16,
+    pub tx: u32,
+    pub asset: String,
+    /// Balances after the transaction, with the decimals of the asset
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+/// Line of the dead-letter file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub url: String,
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+    pub webhook: Webhook,
+}
+
+/// Signature of the body signed at the timestamp, as in the signature header
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("Any key length is fine");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Checks the signature header of a received webhook, in constant time
+pub fn verify(secret: &str, timestamp: u64, body: &[u8], signature: &str) -> bool {
+    let expected = sign(secret, timestamp, body);
+
+    expected.len() == signature.len()
+        && expected.bytes().zip(signature.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Turns the events of the engine into webhooks and queues them for the delivery
+pub struct Notifier {
+    config: Arc<Webhooks>,
+    queue: mpsc::Sender<Webhook>,
+    /// Webhooks which didn't fit into the queue, written by the delivery
+    letters: mpsc::UnboundedSender<DeadLetter>,
+    /// Accounts seen locked, to send `account-locked` only once per account
+    locked: RefCell<FxHashSet<u16>>,
+    /// Prefix of the ids, unique per process start
+    started: u64,
+    sent: RefCell<u64>,
+}
+
+impl Notifier {
+
+    /// Notifier and the delivery, which runs when awaited, till the notifier is dropped and the queue is empty
+    pub fn new(config: &Webhooks) -> (Self, impl Future<Output = ()>) {
+        let config = Arc::new(config.clone());
+        let (queue, rx) = mpsc::channel(config.queue.max(1));
+        let (letters, letters_rx) = mpsc::unbounded_channel();
+        let notifier = Self {
+            config: config.clone(),
+            queue,
+            letters: letters.clone(),
+            locked: RefCell::default(),
+            started: now_ms(),
+            sent: RefCell::default(),
+        };
+
+        let delivery = async move {
+            futures::join!(deliver(rx, letters, config.clone()), write_dead_letters(letters_rx, config));
+        };
+        (notifier, delivery)
+    }
+
+    /// Queues the webhooks of the event, if any. Never waits: the ones which don't fit are handed to the delivery
+    /// for the dead-letter file
+    pub fn notify(&self, event: &AccountEvent) {
+        let mut kinds = Vec::new();
+        // Not for the counterparty of a transfer or the house account, the dispute isn't theirs
+        if event.outcome == "ok" && event.client == event.origin {
+            match event.r#type {
+                TransactionType::Dispute => kinds.push(WebhookKind::DisputeOpened),
+                TransactionType::Chargeback => kinds.push(WebhookKind::Chargeback),
+                _ => {},
+            }
+        }
+        if event.locked && self.locked.borrow_mut().insert(event.client) {
+            kinds.push(WebhookKind::AccountLocked);
+        }
+
+        for kind in kinds.into_iter().filter(|kind| self.config.events.contains(kind)) {
+            let mut sent = self.sent.borrow_mut();
+            *sent += 1;
+
+            // The delivery stops only when the notifier is dropped, so the queue can only be full
+            let queued = self.queue.try_send(Webhook {
+                id: format!("{}-{}", self.started, sent),
+                event: kind,
+                client: event.client,
+                tx: event.tx,
+                asset: event.asset.clone(),
+                available: event.available.clone(),
+                held: event.held.clone(),
+                total: event.total.clone(),
+                locked: event.locked,
+            });
+            if let Err(TrySendError::Full(webhook) | TrySendError::Closed(webhook)) = queued {
+                if self.config.dead_letter.is_some() {
+                    for url in &self.config.urls {
+                        let letter = DeadLetter { url: url.clone(), attempts: 0, error: "Queue is full".to_string(), webhook: webhook.clone() };
+                        // Only fails if the delivery is gone, nothing would write it then
+                        let _ = self.letters.send(letter);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Attempt of sending a webhook to one of the urls
+struct Delivery {
+    due: Instant,
+    url: usize,
+    attempt: u32,
+    webhook: Arc<Webhook>,
+}
+
+/// Earliest due first, in the heap
+impl Ord for Delivery {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.due.cmp(&self.due)
+    }
+}
+
+impl PartialOrd for Delivery {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due
+    }
+}
+
+impl Eq for Delivery {}
+
+/// Sends the queued webhooks to every url, the attempts run concurrently. Failed attempts go back into the retry queue
+/// with an exponential backoff, the webhooks failed after all the retries go to the dead-letter file.
+/// New webhooks are taken off the queue only while the attempts in flight and waiting for a retry fit into it
+async fn deliver(mut rx: mpsc::Receiver<Webhook>, letters: mpsc::UnboundedSender<DeadLetter>, config: Arc<Webhooks>) {
+    let client = Client::new();
+    let mut retries: BinaryHeap<Delivery> = BinaryHeap::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut open = true;
+
+    while open || !retries.is_empty() || !in_flight.is_empty() {
+        let next = retries.peek().map(|d| d.due);
+        let room = in_flight.len() + retries.len() < config.queue.max(1);
+
+        tokio::select! {
+            webhook = rx.recv(), if open && room => {
+                match webhook {
+                    Some(webhook) => {
+                        let webhook = Arc::new(webhook);
+                        for url in 0..config.urls.len() {
+                            let delivery = Delivery { due: Instant::now(), url, attempt: 0, webhook: webhook.clone() };
+                            in_flight.push(attempt(&client, &config, &letters, delivery));
+                        }
+                    },
+                    None => open = false,
+                }
+            },
+            Some(retry) = in_flight.next(), if !in_flight.is_empty() => retries.extend(retry),
+            _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                if let Some(delivery) = retries.pop() {
+                    in_flight.push(attempt(&client, &config, &letters, delivery));
+                }
+            },
+        }
+    }
+}
+
+/// Sends the webhook once. Returns the retry on a failure, or hands it to the dead-letter file if there are no retries left
+async fn attempt(client: &Client<HttpConnector>, config: &Webhooks, letters: &mpsc::UnboundedSender<DeadLetter>, mut delivery: Delivery) -> Option<Delivery> {
+    let url = &config.urls[delivery.url];
+    let timeout = Duration::from_millis(config.timeout_ms);
+
+    let error = match tokio::time::timeout(timeout, send(client, url, &delivery.webhook, config.secret.as_deref())).await {
+        Ok(Ok(())) => return None,
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => "Timed out".to_string(),
+    };
+
+    if delivery.attempt < config.retries {
+        delivery.due = Instant::now() + Duration::from_millis(config.backoff_ms) * 2u32.saturating_pow(delivery.attempt);
+        delivery.attempt += 1;
+        return Some(delivery)
+    }
+
+    if config.dead_letter.is_some() {
+        let _ = letters.send(DeadLetter { url: url.clone(), attempts: delivery.attempt + 1, error, webhook: (*delivery.webhook).clone() });
+    }
+    None
+}
+
+/// Appends the webhooks, which weren't delivered, to the dead-letter file one by one, off the runtime threads,
+/// till the notifier and the delivery are gone
+async fn write_dead_letters(mut letters: mpsc::UnboundedReceiver<DeadLetter>, config: Arc<Webhooks>) {
+    while let Some(letter) = letters.recv().await {
+        let config = config.clone();
+        let written = tokio::task::spawn_blocking(move || dead_letter(&config, letter)).await;
+        if let Err(e) = written {
+            eprintln!("E: dead-letter file failed: {}", e);
+        }
+    }
+}
+
+/// Appends the webhook, which wasn't delivered to the url, to the dead-letter file, if it's set
+fn dead_letter(config: &Webhooks, letter: DeadLetter) {
+    if let Some(location) = &config.dead_letter {
+        let written = serde_json::to_string(&letter).map_err(|e| e.to_string()).and_then(|line| {
+            OpenOptions::new().create(true).append(true).open(location)
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = written {
+            eprintln!("E: webhook {} is lost, dead-letter file failed: {}", letter.webhook.id, e);
+        }
+    }
+}
+
+/// Posts the webhook, any status but 2xx is an error
+async fn send(client: &Client<HttpConnector>, url: &str, webhook: &Webhook, secret: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let body = serde_json::to_vec(webhook)?;
+    let timestamp = now_ms();
+
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header("content-type", "application/json")
+        .header(TIMESTAMP_HEADER, timestamp);
+    if let Some(secret) = secret {
+        req = req.header(SIGNATURE_HEADER, sign(secret, timestamp, &body));
+    }
+
+    let resp = client.request(req.body(Body::from(body))?).await?;
+    if !resp.status().is_success() {
+        return Err(format!("Status {}", resp.status()).into())
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::db::Db;
+    use crate::db::transaction::Transaction;
+
+    use rust_decimal_macros::dec;
+    use warp::Filter;
+
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn event(r#type: TransactionType, client: u16, locked: bool, outcome: &str) -> AccountEvent {
+        AccountEvent {
+            client,
+            origin: client,
+            tx: 1,
+            r#type,
+            asset: "".to_string(),
+            available: "0.0000".to_string(),
+            held: "0.0000".to_string(),
+            total: "0.0000".to_string(),
+            locked,
+            outcome: outcome.to_string(),
+        }
+    }
+
+    /// Timestamp, signature and body of the requests a receiver got
+    type Received = Arc<Mutex<Vec<(u64, String, Vec<u8>)>>>;
+
+    /// Local stand-in of a receiver, failing the first `failures` requests with 500.
+    /// Returns its url, the requests it got and the amount of them
+    fn receiver(failures: usize) -> (String, Received, Arc<AtomicUsize>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let (store, counter) = (received.clone(), count.clone());
+        let route = warp::post()
+            .and(warp::header::<u64>(TIMESTAMP_HEADER))
+            .and(warp::header::<String>(SIGNATURE_HEADER))
+            .and(warp::body::bytes())
+            .map(move |timestamp: u64, signature: String, body: bytes::Bytes| {
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    return warp::http::StatusCode::INTERNAL_SERVER_ERROR
+                }
+                store.lock().unwrap().push((timestamp, signature, body.to_vec()));
+                warp::http::StatusCode::OK
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (format!("http://{}/", addr), received, count)
+    }
+
+    fn config(urls: Vec<String>, dead_letter: Option<PathBuf>) -> Webhooks {
+        Webhooks {
+            urls,
+            secret: Some("secret".to_string()),
+            retries: 2,
+            backoff_ms: 10,
+            dead_letter,
+            ..Webhooks::default()
+        }
+    }
+
+    #[test]
+    fn signatures() {
+        let signature = sign("secret", 1, b"{}");
+        assert!(signature.starts_with("sha256="));
+        assert!(verify("secret", 1, b"{}", &signature));
+        assert!(!verify("secret", 2, b"{}", &signature));
+        assert!(!verify("other", 1, b"{}", &signature));
+    }
+
+    #[tokio::test]
+    async fn kinds_of_the_events() {
+        let (notifier, _) = Notifier::new(&Webhooks::default());
+        let (tx, mut rx) = mpsc::channel(16);
+        let notifier = Notifier { queue: tx, ..notifier };
+
+        notifier.notify(&event(TransactionType::Deposit, 1, false, "ok"));
+        notifier.notify(&event(TransactionType::Dispute, 1, false, "Err"));
+        notifier.notify(&event(TransactionType::Dispute, 1, false, "ok"));
+        notifier.notify(&event(TransactionType::Chargeback, 1, true, "ok"));
+        // Locked already
+        notifier.notify(&event(TransactionType::Deposit, 1, true, "Err"));
+        drop(notifier);
+
+        let mut kinds = Vec::new();
+        while let Some(webhook) = rx.recv().await {
+            kinds.push(webhook.event);
+        }
+        assert_eq!(kinds, vec![WebhookKind::DisputeOpened, WebhookKind::Chargeback, WebhookKind::AccountLocked]);
+    }
+
+    #[tokio::test]
+    async fn only_the_client_of_the_dispute() {
+        let config: Config = serde_json::from_str(r#"{
+            "fees": {"house_account": 0, "fees": {"withdrawal": {"flat": 1}, "chargeback": {"flat": 2}}}
+        }"#).unwrap();
+        let (notifier, _) = Notifier::new(&Webhooks::default());
+        let (tx, mut rx) = mpsc::channel(16);
+        let notifier = Notifier { queue: tx, ..notifier };
+
+        let mut db = Db::new(&config).with_events(move |event| notifier.notify(&event));
+        db.process_new_transaction(Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)), false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)), false)).unwrap();
+        // Both hold and charge back the fee at the house account too
+        db.process_new_transaction(Transaction::new(TransactionType::Dispute, 1, 2, None, false)).unwrap();
+        db.process_new_transaction(Transaction::new(TransactionType::Chargeback, 1, 2, None, false)).unwrap();
+        drop(db);
+
+        let mut webhooks = Vec::new();
+        while let Some(webhook) = rx.recv().await {
+            webhooks.push((webhook.event, webhook.client));
+        }
+        assert_eq!(webhooks, vec![
+            (WebhookKind::DisputeOpened, 1),
+            (WebhookKind::Chargeback, 1),
+            (WebhookKind::AccountLocked, 1),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn retries_and_signs() {
+        let (url, received, count) = receiver(2);
+        let (notifier, delivery) = Notifier::new(&config(vec![url], None));
+
+        notifier.notify(&event(TransactionType::Chargeback, 7, true, "ok"));
+        drop(notifier);
+        delivery.await;
+
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+        let received = received.lock().unwrap();
+        let mut kinds: Vec<_> = received.iter().map(|(timestamp, signature, body)| {
+            assert!(verify("secret", *timestamp, body, signature));
+            serde_json::from_slice::<Webhook>(body).unwrap().event
+        }).collect();
+        // Both failed once, sent at the same time
+        kinds.sort_by_key(|kind| *kind as u8);
+        assert_eq!(kinds, vec![WebhookKind::AccountLocked, WebhookKind::Chargeback]);
+    }
+
+    #[tokio::test]
+    async fn slow_url_doesnt_hold_up_the_others() {
+        // Accepts the connections, but never answers
+        let slow = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let (url, received, _) = receiver(0);
+        let (notifier, delivery) = Notifier::new(&config(vec![format!("http://{}/", slow.local_addr().unwrap()), url], None));
+        tokio::spawn(delivery);
+
+        for tx in 0..3 {
+            notifier.notify(&AccountEvent { tx, ..event(TransactionType::Dispute, 1, false, "ok") });
+        }
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while received.lock().unwrap().len() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("Waits for the slow url");
+    }
+
+    #[tokio::test]
+    async fn full_queue_goes_to_the_dead_letter() {
+        let dead_letter = std::env::temp_dir().join(format!("case-full-queue-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&dead_letter);
+
+        let config = Webhooks { queue: 1, ..config(vec!["http://127.0.0.1:1/".to_string()], Some(dead_letter.clone())) };
+        let (notifier, delivery) = Notifier::new(&config);
+        notifier.notify(&event(TransactionType::Dispute, 1, false, "ok"));
+        notifier.notify(&event(TransactionType::Dispute, 2, false, "ok"));
+        // Nothing is written by the notifier itself
+        assert!(!dead_letter.exists());
+        drop(notifier);
+        delivery.await;
+
+        let letters: Vec<(u32, u16)> = std::fs::read_to_string(&dead_letter).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<DeadLetter>(line).unwrap())
+            .map(|letter| (letter.attempts, letter.webhook.client))
+            .collect();
+        // The one queued failed every attempt
+        assert_eq!(letters, vec![(0, 2), (1 + 2, 1)]);
+
+        std::fs::remove_file(dead_letter).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dead_letters() {
+        let (url, received, count) = receiver(usize::MAX);
+        let dead_letter = std::env::temp_dir().join(format!("case-dead-letter-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&dead_letter);
+
+        let (notifier, delivery) = Notifier::new(&config(vec![url.clone()], Some(dead_letter.clone())));
+        notifier.notify(&event(TransactionType::Dispute, 3, false, "ok"));
+        drop(notifier);
+        delivery.await;
+
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert!(received.lock().unwrap().is_empty());
+
+        let letters: Vec<DeadLetter> = std::fs::read_to_string(&dead_letter).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].url, url);
+        assert_eq!(letters[0].attempts, 3);
+        assert_eq!(letters[0].webhook.event, WebhookKind::DisputeOpened);
+
+        std::fs::remove_file(dead_letter).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+use rust_decimal::Decimal;
+
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::db::transaction::{Transaction, TransactionType};
+
+
+/// Content type of a streamed http body of frames
+pub const CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Bytes of the length prefix of a frame
+pub const PREFIX: usize = 2;
+
+/// Bytes of the fixed part of a frame: type, flags, client, tx and the amount
+pub const FIXED: usize = 24;
+
+const AMOUNT: u8 = 1;
+const COUNTERPARTY: u8 = 1 << 1;
+const TIMESTAMP: u8 = 1 << 2;
+const ASSET: u8 = 1 << 3;
+
+/// Bits of the decimal flags, which can be set: the sign and the scale
+const DECIMAL_FLAGS: u32 = 0x80FF_0000;
+const MAX_SCALE: u32 = 28;
+
+/// Status written back for every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Applied = 0,
+    /// Decoded, but the engine returned an error
+    Rejected = 1,
+    /// Couldn't be decoded
+    Malformed = 2,
+}
+
+/// Represents an error of encoding or decoding a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// Frame is shorter than its fields - contains the length of the frame
+    Truncated(usize),
+    /// Frame is longer than its fields - contains the bytes left
+    TrailingBytes(usize),
+    UnknownType(u8),
+    UnknownFlags(u8),
+    InvalidAmount,
+    InvalidAsset,
+    /// Asset doesn't fit into a frame - contains its length
+    AssetTooLong(usize),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::Truncated(len) => {
+                write!(f, "Frame of {} bytes is shorter than its fields", len)
+            },
+            WireError::TrailingBytes(left) => {
+                write!(f, "Frame has {} bytes after its fields", left)
+            },
+            WireError::UnknownType(r#type) => {
+                write!(f, "Unknown transaction type: {}", r#type)
+            },
+            WireError::UnknownFlags(flags) => {
+                write!(f, "Unknown flags: {:#010b}", flags)
+            },
+            WireError::InvalidAmount => {
+                write!(f, "Amount is not a valid decimal")
+            },
+            WireError::InvalidAsset => {
+                write!(f, "Asset is not valid utf-8")
+            },
+            WireError::AssetTooLong(len) => {
+                write!(f, "Asset of {} bytes is longer than 255 bytes", len)
+            },
+        }
+    }
+}
+
+fn type_to_byte(r#type: &TransactionType) -> u8 {
+    match r#type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Transfer => 5,
+    }
+}
+
+fn type_from_byte(byte: u8) -> Result<TransactionType, WireError> {
+    match byte {
+        0 => Ok(TransactionType::Deposit),
+        1 => Ok(TransactionType::Withdrawal),
+        2 => Ok(TransactionType::Dispute),
+        3 => Ok(TransactionType::Resolve),
+        4 => Ok(TransactionType::Chargeback),
+        5 => Ok(TransactionType::Transfer),
+        _ => Err(WireError::UnknownType(byte)),
+    }
+}
+
+/// Appends the transaction as a frame: the length of the rest as u16, then the fixed part and the optional fields.
+/// Everything is little-endian, see the README for the layout
+pub fn encode_into(t: &Transaction, out: &mut Vec<u8>) -> Result<(), WireError> {
+    let asset = t.asset();
+    if asset.len() > u8::MAX as usize {
+        return Err(WireError::AssetTooLong(asset.len()))
+    }
+
+    let mut flags = 0;
+    if t.amount().is_some() { flags |= AMOUNT }
+    if t.counterparty().is_some() { flags |= COUNTERPARTY }
+    if t.timestamp().is_some() { flags |= TIMESTAMP }
+    if !asset.is_empty() { flags |= ASSET }
+
+    let start = out.len();
+    out.extend_from_slice(&[0; PREFIX]);
+    out.push(type_to_byte(t.get_type()));
+    out.push(flags);
+    out.extend_from_slice(&t.client().to_le_bytes());
+    out.extend_from_slice(&t.tx().to_le_bytes());
+    out.extend_from_slice(&t.amount().unwrap_or_default().serialize());
+    if let Some(counterparty) = t.counterparty() {
+        out.extend_from_slice(&counterparty.to_le_bytes());
+    }
+    if let Some(timestamp) = t.timestamp() {
+        out.extend_from_slice(&timestamp.to_le_bytes());
+    }
+    if !asset.is_empty() {
+        out.push(asset.len() as u8);
+        out.extend_from_slice(asset.as_bytes());
+    }
+
+    // At most 24 + 2 + 8 + 1 + 255 bytes, fits
+    let len = (out.len() - start - PREFIX) as u16;
+    out[start..start + PREFIX].copy_from_slice(&len.to_le_bytes());
+
+    Ok(())
+}
+
+/// Transaction as a frame, with the length prefix
+pub fn encode(t: &Transaction) -> Result<Vec<u8>, WireError> {
+    let mut out = Vec::with_capacity(PREFIX + FIXED);
+    encode_into(t, &mut out)?;
+    Ok(out)
+}
+
+/// Splits off `n` bytes of the frame
+fn take<'a>(rest: &mut &'a [u8], n: usize, len: usize) -> Result<&'a [u8], WireError> {
+    if rest.len() < n {
+        return Err(WireError::Truncated(len))
+    }
+    let (head, tail) = rest.split_at(n);
+    *rest = tail;
+    Ok(head)
+}
+
+/// Decodes the frame without its length prefix
+pub fn decode(frame: &[u8]) -> Result<Transaction, WireError> {
+    let len = frame.len();
+    let mut rest = frame;
+
+    let fixed = take(&mut rest, FIXED, len)?;
+    let r#type = type_from_byte(fixed[0])?;
+    let flags = fixed[1];
+    if flags & !(AMOUNT | COUNTERPARTY | TIMESTAMP | ASSET) != 0 {
+        return Err(WireError::UnknownFlags(flags))
+    }
+    let client = u16::from_le_bytes([fixed[2], fixed[3]]);
+    let tx = u32::from_le_bytes(fixed[4..8].try_into().expect("4 bytes"));
+
+    let amount = if flags & AMOUNT != 0 {
+        let bytes: [u8; 16] = fixed[8..24].try_into().expect("16 bytes");
+        let decimal_flags = u32::from_le_bytes(bytes[..4].try_into().expect("4 bytes"));
+        if decimal_flags & !DECIMAL_FLAGS != 0 || (decimal_flags >> 16) & 0xFF > MAX_SCALE {
+            return Err(WireError::InvalidAmount)
+        }
+        Some(Decimal::deserialize(bytes))
+    } else {
+        None
+    };
+
+    let mut t = Transaction::new(r#type, client, tx, amount, false);
+
+    if flags & COUNTERPARTY != 0 {
+        let bytes = take(&mut rest, 2, len)?;
+        t = t.with_counterparty(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+    if flags & TIMESTAMP != 0 {
+        let bytes = take(&mut rest, 8, len)?;
+        t = t.with_timestamp(u64::from_le_bytes(bytes.try_into().expect("8 bytes")));
+    }
+    if flags & ASSET != 0 {
+        let n = take(&mut rest, 1, len)?[0] as usize;
+        let asset = std::str::from_utf8(take(&mut rest, n, len)?).map_err(|_| WireError::InvalidAsset)?;
+        t = t.with_asset(asset);
+    }
+
+    if !rest.is_empty() {
+        return Err(WireError::TrailingBytes(rest.len()))
+    }
+
+    Ok(t)
+}
+
+/// Frames of a byte stream, which comes in arbitrary chunks. Iterates over the complete frames pushed so far,
+/// an incomplete one waits for the next chunk. A malformed frame doesn't break the stream, its length is still known
+#[derive(Debug, Default)]
+pub struct Frames {
+    buf: Vec<u8>,
+    /// Start of the first frame, which isn't read yet
+    start: usize,
+}
+
+impl Frames {
+
+    /// Constructor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next chunk of the stream
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Bytes of an incomplete frame. The stream was cut in the middle of a frame, if it isn't `0` at the end
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.start
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Result<Transaction, WireError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.start..];
+        if rest.len() < PREFIX {
+            return None
+        }
+
+        let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        let frame = rest.get(PREFIX..PREFIX + len)?;
+        let result = decode(frame);
+        self.start += PREFIX + len;
+
+        Some(result)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn fields(t: &Transaction) -> (TransactionType, u16, u32, Option<Decimal>, String, Option<u16>, Option<u64>) {
+        (t.get_type().clone(), t.client(), t.tx(), t.amount(), t.asset().to_string(), t.counterparty(), t.timestamp())
+    }
+
+    fn transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(1.2345)), false),
+            Transaction::new(TransactionType::Withdrawal, u16::MAX, u32::MAX, Some(dec!(-79228162514264337593543950335)), false),
+            Transaction::new(TransactionType::Dispute, 1, 1, None, false),
+            Transaction::new(TransactionType::Transfer, 1, 2, Some(dec!(0.0000000000000000000000000001)), false)
+                .with_counterparty(2)
+                .with_timestamp(1_700_000_000_000)
+                .with_asset("EUR"),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for t in transactions() {
+            let frame = encode(&t).unwrap();
+            assert_eq!(frame.len() - PREFIX, u16::from_le_bytes([frame[0], frame[1]]) as usize);
+            assert_eq!(fields(&decode(&frame[PREFIX..]).unwrap()), fields(&t));
+        }
+
+        let plain = encode(&transactions()[0]).unwrap();
+        assert_eq!(plain.len(), PREFIX + FIXED);
+    }
+
+    #[test]
+    fn stream_in_chunks() {
+        let mut stream = Vec::new();
+        for t in transactions() {
+            encode_into(&t, &mut stream).unwrap();
+        }
+        // Malformed frame in the middle, the next ones are still read
+        stream.extend_from_slice(&[3, 0, 9, 9, 9]);
+        encode_into(&transactions()[0], &mut stream).unwrap();
+
+        let mut frames = Frames::new();
+        let mut decoded = Vec::new();
+        for chunk in stream.chunks(7) {
+            frames.push(chunk);
+            decoded.extend(frames.by_ref());
+        }
+
+        assert_eq!(frames.pending(), 0);
+        assert_eq!(decoded.len(), 6);
+        assert_eq!(decoded[4].as_ref().unwrap_err(), &WireError::Truncated(3));
+        assert_eq!(fields(decoded[5].as_ref().unwrap()), fields(&transactions()[0]));
+    }
+
+    #[test]
+    fn malformed() {
+        let mut frame = encode(&transactions()[0]).unwrap().split_off(PREFIX);
+
+        frame[0] = 6;
+        assert_eq!(decode(&frame).unwrap_err(), WireError::UnknownType(6));
+        frame[0] = 0;
+
+        frame[1] = 0b1_0001;
+        assert_eq!(decode(&frame).unwrap_err(), WireError::UnknownFlags(0b1_0001));
+        frame[1] = AMOUNT;
+
+        // Scale of 29
+        frame[10] = 29;
+        assert_eq!(decode(&frame).unwrap_err(), WireError::InvalidAmount);
+        frame[10] = 4;
+
+        frame.push(0);
+        assert_eq!(decode(&frame).unwrap_err(), WireError::TrailingBytes(1));
+        frame.pop();
+
+        // Asset flag without the asset
+        frame[1] = AMOUNT | ASSET;
+        assert_eq!(decode(&frame).unwrap_err(), WireError::Truncated(FIXED));
+
+        let long = "X".repeat(256);
+        assert_eq!(encode(&transactions().remove(0).with_asset(&long)).unwrap_err(), WireError::AssetTooLong(256));
+    }
+}
@@ -0,0 +1,174 @@
+//! Differential tests: the same transactions through the batch mode (`db_from_file`) and through the server
+//! should end up in the same state, as long as the order inside of every client is kept.
+
+use case::{bind_server, bind_server_with, db_from_file, diff_states, fetch_state, read_csv_lines, run_server_replay, send_lines};
+use case::fuzzing::gen_line;
+use case::config::Config;
+use case::Listeners;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const LINES: usize = 4096;
+
+/// Environment variable with the seed of the generated files, a random one is used otherwise
+const SEED_VAR: &str = "DIFFERENTIAL_SEED";
+
+/// Writes a generated csv into a temp file, removed on drop. The seed is printed if the test fails
+struct GeneratedFile {
+    path: PathBuf,
+    seed: u64,
+}
+
+impl GeneratedFile {
+    fn new(name: &str, n: usize) -> Self {
+        let path = std::env::temp_dir().join(format!("case-{}-{}.csv", name, std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+
+        let seed = match std::env::var(SEED_VAR) {
+            Ok(seed) => seed.parse().expect("Seed should be a number"),
+            Err(_) => rand::random(),
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+        writeln!(file, "type,client,tx,amount").unwrap();
+        for _ in 0..n {
+            writeln!(file, "{}", gen_line(&mut rng)).unwrap();
+        }
+
+        Self { path, seed }
+    }
+
+    fn location(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for GeneratedFile {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!("Generated file {} with {}={}", self.path.display(), SEED_VAR, self.seed);
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Starts a fresh server on a random port, returns its url
+fn start_server() -> String {
+    let (addr, server) = bind_server(([127, 0, 0, 1], 0), &Config::default(), false);
+    tokio::spawn(server);
+
+    format!("http://{}/", addr)
+}
+
+/// Randomly interleaves the lines of different clients, keeping the order inside of every client
+fn interleave(lines: &[(String, String)], rng: &mut StdRng) -> Vec<String> {
+    let mut by_client: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (client, line) in lines {
+        by_client.entry(client).or_default().push(line);
+    }
+
+    let mut queues: Vec<std::vec::IntoIter<&str>> = by_client.into_values().map(|v| v.into_iter()).collect();
+    let mut result = Vec::with_capacity(lines.len());
+    while !queues.is_empty() {
+        let i = rng.gen_range(0..queues.len());
+        match queues[i].next() {
+            Some(line) => result.push(line.to_string()),
+            None => { queues.swap_remove(i); },
+        }
+    }
+
+    result
+}
+
+/// Sends the lines in order to a fresh server and compares its state with the expected one
+async fn assert_same_state(lines: Vec<String>, expected: &str) {
+    let url = start_server();
+
+    send_lines(&url, 0, lines, false).await.unwrap();
+    let actual = fetch_state(&url).await.unwrap();
+
+    let diff = diff_states(expected, &actual);
+    assert!(diff.is_empty(), "states differ:\n{}", diff.join("\n"));
+}
+
+#[tokio::test]
+async fn server_agrees_with_batch_in_file_order() {
+    let file = GeneratedFile::new("in-order", LINES);
+    let expected = format!("{}", db_from_file(file.location(), &Config::default(), false).unwrap());
+
+    let lines = read_csv_lines(file.location()).unwrap().into_iter().map(|(_, line)| line).collect();
+
+    assert_same_state(lines, &expected).await;
+}
+
+#[tokio::test]
+async fn server_agrees_with_batch_in_interleaved_orders() {
+    let file = GeneratedFile::new("interleaved", LINES);
+    let expected = format!("{}", db_from_file(file.location(), &Config::default(), false).unwrap());
+
+    let lines = read_csv_lines(file.location()).unwrap();
+
+    for seed in 0..4 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        assert_same_state(interleave(&lines, &mut rng), &expected).await;
+    }
+}
+
+#[tokio::test]
+async fn server_agrees_with_batch_when_sharded_concurrently() {
+    let file = GeneratedFile::new("sharded", LINES);
+
+    for concurrent in [2, 16] {
+        let url = start_server();
+        run_server_replay(&url, file.location(), &Config::default(), concurrent, false, false).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn line_listener_agrees_with_batch() {
+    let file = GeneratedFile::new("lines", LINES);
+    let expected = format!("{}", db_from_file(file.location(), &Config::default(), false).unwrap());
+
+    let listeners = Listeners { lines: Some(([127, 0, 0, 1], 0).into()), ..Listeners::default() };
+    let (addr, bound, server) = bind_server_with(([127, 0, 0, 1], 0), listeners, &Config::default(), false);
+    tokio::spawn(server);
+
+    let lines: Vec<String> = read_csv_lines(file.location()).unwrap().into_iter().map(|(_, line)| line).collect();
+    let (mut reader, mut writer) = TcpStream::connect(bound.lines.unwrap()).await.unwrap().into_split();
+    let stream = lines.join("\n");
+    let writing = tokio::spawn(async move {
+        writer.write_all(stream.as_bytes()).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+
+    let mut responses = String::new();
+    reader.read_to_string(&mut responses).await.unwrap();
+    writing.await.unwrap();
+    assert_eq!(responses.lines().count(), lines.len());
+
+    let actual = fetch_state(&format!("http://{}/", addr)).await.unwrap();
+    let diff = diff_states(&expected, &actual);
+    assert!(diff.is_empty(), "states differ:\n{}", diff.join("\n"));
+}
+
+#[tokio::test]
+async fn replay_follows_the_header() {
+    let path = std::env::temp_dir().join(format!("case-header-{}.csv", std::process::id()));
+    std::fs::write(&path, "client,type,tx,amount,timestamp,note\n1,deposit,1,2.0,100,x\n1,withdrawal,2,1.5,,y\n1,dispute,1,,300,\n").unwrap();
+    let location = path.to_str().unwrap();
+
+    let lines: Vec<String> = read_csv_lines(location).unwrap().into_iter().map(|(_, line)| line).collect();
+    assert_eq!(lines, vec!["deposit,1,1,2.0,,,100", "withdrawal,1,2,1.5", "dispute,1,1,,,,300"]);
+
+    let expected = format!("{}", db_from_file(location, &Config::default(), false).unwrap());
+    assert_same_state(lines, &expected).await;
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,229 @@
+//! Server tests over the real listeners: the binary frames over http and raw TCP, the idempotency keys, the server-sent events,
+//! the load runs of the fuzzer.
+
+use case::{bind_server_with, run_server_fuzz, send_lines, Listeners, LoadConfig, RetryPolicy};
+use case::config::Config;
+use case::db::transaction::{Transaction, TransactionType};
+use case::wire::{self, Status};
+
+use hyper::{Body, Client, Method, Request};
+use hyper::body::HttpBody;
+use rust_decimal_macros::dec;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Starts a fresh server with the TCP listeners, returns the http address and the bound listeners
+fn start_server() -> (SocketAddr, Listeners) {
+    let listeners = Listeners {
+        wire: Some(([127, 0, 0, 1], 0).into()),
+        lines: Some(([127, 0, 0, 1], 0).into()),
+    };
+    let (addr, bound, server) = bind_server_with(([127, 0, 0, 1], 0), listeners, &Config::default(), false);
+    tokio::spawn(server);
+
+    (addr, bound)
+}
+
+/// Subscribes to the events, the subscription is made once the response is received
+async fn subscribe(addr: SocketAddr, query: &str) -> Body {
+    let response = Client::new().get(format!("http://{}/events{}", addr, query).parse().unwrap()).await.unwrap();
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    response.into_body()
+}
+
+/// Reads the next server-sent event: its name and data. Keep-alive comments are skipped
+async fn next_event(body: &mut Body, buf: &mut String) -> (String, String) {
+    loop {
+        if let Some(end) = buf.find("\n\n") {
+            let block: String = buf.drain(..end + 2).collect();
+            let mut event = (String::new(), String::new());
+            for line in block.lines() {
+                match line.split_once(':') {
+                    Some(("event", name)) => event.0 = name.trim().to_string(),
+                    Some(("data", data)) => event.1 = data.trim().to_string(),
+                    _ => {},
+                }
+            }
+            if !event.0.is_empty() {
+                return event
+            }
+            continue
+        }
+
+        let chunk = timeout(Duration::from_secs(10), body.data()).await
+            .expect("No event in time").expect("The stream ended").unwrap();
+        buf.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+}
+
+fn deposit(tx: u32) -> Vec<u8> {
+    wire::encode(&Transaction::new(TransactionType::Deposit, 1, tx, Some(dec!(1)), false)).unwrap()
+}
+
+#[tokio::test]
+async fn http_statuses_are_streamed_back() {
+    let (addr, _) = start_server();
+
+    let (mut sender, body) = Body::channel();
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/", addr))
+        .header("content-type", wire::CONTENT_TYPE)
+        .body(body)
+        .unwrap();
+    let response = tokio::spawn(Client::new().request(req));
+
+    sender.send_data(deposit(1).into()).await.unwrap();
+    let mut body = response.await.unwrap().unwrap().into_body();
+    // The status comes while the body is still open
+    let first = timeout(Duration::from_secs(5), body.data()).await
+        .expect("The status isn't streamed").unwrap().unwrap();
+    assert_eq!(first.as_ref(), &[Status::Applied as u8]);
+
+    // A duplicate, then a frame cut in the middle
+    let mut rest = deposit(1);
+    rest.extend_from_slice(&deposit(2)[..10]);
+    sender.send_data(rest.into()).await.unwrap();
+    drop(sender);
+
+    let rest = hyper::body::to_bytes(body).await.unwrap();
+    assert_eq!(rest.as_ref(), &[Status::Rejected as u8, Status::Malformed as u8]);
+}
+
+#[tokio::test]
+async fn retried_idempotency_key_gets_the_same_response() {
+    let (addr, _) = start_server();
+
+    let post = |body: &'static str| {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/", addr))
+            .header("content-type", "application/json")
+            .header("idempotency-key", "7f3c")
+            .body(Body::from(body))
+            .unwrap();
+        async move {
+            let response = Client::new().request(req).await.unwrap();
+            String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+        }
+    };
+    let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.0"}"#;
+
+    assert_eq!(post(deposit).await, "OK");
+    // The retry gets the remembered response instead of being rejected as a duplicate
+    assert_eq!(post(deposit).await, "OK");
+    assert!(post(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": "1.0"}"#).await.contains("different request"));
+
+    let history = Client::new().get(format!("http://{}/history/1", addr).parse().unwrap()).await.unwrap();
+    let history = hyper::body::to_bytes(history.into_body()).await.unwrap();
+    assert_eq!(std::str::from_utf8(&history).unwrap().lines().filter(|line| line.contains("deposit")).count(), 1);
+}
+
+#[tokio::test]
+async fn tcp_reports_an_incomplete_frame() {
+    let (_, listeners) = start_server();
+
+    let mut frames = deposit(1);
+    frames.extend_from_slice(&deposit(2)[..5]);
+
+    let mut socket = TcpStream::connect(listeners.wire.unwrap()).await.unwrap();
+    socket.write_all(&frames).await.unwrap();
+    socket.shutdown().await.unwrap();
+
+    let mut statuses = Vec::new();
+    socket.read_to_end(&mut statuses).await.unwrap();
+    assert_eq!(statuses, vec![Status::Applied as u8, Status::Malformed as u8]);
+}
+
+#[tokio::test]
+async fn events_are_filtered_by_client() {
+    let (addr, _) = start_server();
+    let mut all = subscribe(addr, "").await;
+    let mut first = subscribe(addr, "?client=1").await;
+
+    let lines = ["deposit,1,1,1.0", "deposit,2,2,2.0", "withdrawal,2,3,5.0", "withdrawal,1,4,0.5"];
+    send_lines(&format!("http://{}/", addr), 0, lines.iter().map(|l| l.to_string()).collect(), false).await.unwrap();
+
+    let mut buf = String::new();
+    let mut clients = Vec::new();
+    for _ in 0..lines.len() {
+        let (name, data) = next_event(&mut all, &mut buf).await;
+        assert_eq!(name, "account");
+        let event: serde_json::Value = serde_json::from_str(&data).unwrap();
+        clients.push(event["client"].as_u64().unwrap());
+    }
+    assert_eq!(clients, vec![1, 2, 2, 1]);
+
+    let mut buf = String::new();
+    let (name, data) = next_event(&mut first, &mut buf).await;
+    let event: serde_json::Value = serde_json::from_str(&data).unwrap();
+    assert_eq!((name.as_str(), event["client"].as_u64(), event["tx"].as_u64()), ("account", Some(1), Some(1)));
+    // The events of client 2 are skipped, including the rejected withdrawal
+    let (_, data) = next_event(&mut first, &mut buf).await;
+    let event: serde_json::Value = serde_json::from_str(&data).unwrap();
+    assert_eq!((event["client"].as_u64(), event["tx"].as_u64(), event["available"].as_str()), (Some(1), Some(4), Some("0.5000")));
+}
+
+#[tokio::test]
+async fn slow_subscriber_gets_lagged() {
+    const DEPOSITS: usize = 100_000;
+
+    let (addr, listeners) = start_server();
+    // Not read until every deposit is applied
+    let mut events = subscribe(addr, "").await;
+
+    let lines: String = (0..DEPOSITS).map(|tx| format!("deposit,1,{},1.0\n", tx)).collect();
+    let (mut reader, mut writer) = TcpStream::connect(listeners.lines.unwrap()).await.unwrap().into_split();
+    let writing = tokio::spawn(async move {
+        writer.write_all(lines.as_bytes()).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+    let mut responses = String::new();
+    reader.read_to_string(&mut responses).await.unwrap();
+    writing.await.unwrap();
+    assert_eq!(responses.lines().count(), DEPOSITS);
+
+    let mut buf = String::new();
+    for _ in 0..DEPOSITS {
+        let (name, data) = next_event(&mut events, &mut buf).await;
+        if name == "lagged" {
+            assert!(data.parse::<u64>().unwrap() > 0);
+            return
+        }
+    }
+    panic!("Every event was received");
+}
+
+#[tokio::test]
+async fn load_run_makes_every_request() {
+    let (addr, _) = start_server();
+    let config = |requests, concurrent, rate, max_in_flight| LoadConfig {
+        url: format!("http://{}/", addr),
+        requests,
+        concurrent,
+        rate,
+        max_in_flight,
+        duration: None,
+        retry: RetryPolicy::default(),
+        statistics: false,
+        report: None,
+    };
+
+    // The remainder is spread across the workers, fewer requests than workers are still made
+    for (requests, concurrent) in [(3, 8), (10, 4)] {
+        let report = run_server_fuzz(&config(requests, concurrent, None, 0)).await.unwrap();
+        assert_eq!((report.requests, report.skipped), (requests as usize, 0));
+    }
+
+    let report = run_server_fuzz(&config(20, 1, Some(1000.0), 100)).await.unwrap();
+    assert_eq!((report.requests, report.skipped), (20, 0));
+    // Nothing may be in flight, every scheduled request is skipped
+    let report = run_server_fuzz(&config(20, 1, Some(1000.0), 0)).await.unwrap();
+    assert_eq!((report.requests, report.skipped), (0, 20));
+}